@@ -3,20 +3,17 @@ extern crate bencher;
 
 use bencher::Bencher;
 use std::sync::{Arc, RwLock};
+use std::thread;
+use mavulator::connection::{self, UorbConnection};
 use mavulator::mav_writer;
 use flighty::simulato::Simulato;
+use uorb_codec::{UorbHeader, UorbMessage};
 
 fn run_sensor_collection(sim: &Arc<RwLock<Simulato>>) {
-    let mut last_slow_cadence_send = 0; //1Hz sensors
-    let mut last_med_cadence_send = 0; //100Hz sensors
-    let mut last_fast_cadence_send = 0; //400Hz sensors
+    let mut reporting = mav_writer::ReportingState::default();
 
     for _i in 0..100 {
-        let _msg_list = mav_writer::collect_messages(&sim,
-                                        &mut last_slow_cadence_send,
-                                        &mut last_med_cadence_send,
-                                        &mut last_fast_cadence_send
-        );
+        let _msg_list = mav_writer::collect_messages(sim, &mut reporting);
     }
 }
 
@@ -25,5 +22,47 @@ fn bench_sensor_collection(b:&mut Bencher) {
     b.iter(||  run_sensor_collection(&sim));
 }
 
-benchmark_group!(benches, bench_sensor_collection);
-benchmark_main!(benches);
\ No newline at end of file
+/// Collect one tick's worth of messages, with every cadence due
+fn collect_one_tick(sim: &Arc<RwLock<Simulato>>) -> Vec<(UorbHeader, UorbMessage)> {
+    let mut reporting = mav_writer::ReportingState::default();
+    mav_writer::collect_messages(sim, &mut reporting)
+}
+
+/// An in-memory sink that discards everything it receives,
+/// so the benches measure encoding and sending rather than the network stack
+fn connect_to_sink() -> connection::ChannelConnection {
+    let (conn, sink) = connection::pair(None);
+    thread::spawn(move || {
+        loop {
+            match sink.recv() {
+                Ok(_msg) => {},
+                Err(ref e) if connection::is_timeout(e) => {},
+                Err(_e) => break,
+            }
+        }
+    });
+    conn
+}
+
+/// Encode and send a tick's messages one `send` at a time
+fn bench_encode_send_each(b:&mut Bencher) {
+    let sim: Arc<RwLock<Simulato>> = Arc::new(RwLock::new(Simulato::new()));
+    let msg_list = collect_one_tick(&sim);
+    let conn = connect_to_sink();
+    b.iter(|| {
+        for (hdr, msg) in msg_list.iter() {
+            conn.send(hdr, msg).unwrap();
+        }
+    });
+}
+
+/// Encode and send a tick's messages as one batch
+fn bench_encode_send_batch(b:&mut Bencher) {
+    let sim: Arc<RwLock<Simulato>> = Arc::new(RwLock::new(Simulato::new()));
+    let msg_list = collect_one_tick(&sim);
+    let conn = connect_to_sink();
+    b.iter(|| conn.send_batch(&msg_list).unwrap());
+}
+
+benchmark_group!(benches, bench_sensor_collection, bench_encode_send_each, bench_encode_send_batch);
+benchmark_main!(benches);
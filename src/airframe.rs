@@ -0,0 +1,215 @@
+use std::error::Error;
+use std::fmt;
+
+use flighty::models::ActuatorControls;
+
+use crate::calibration::PwmCalibration;
+
+/// Number of channels in `ActuatorOutputs` and `ActuatorControls`
+pub const NUM_CHANNELS: usize = 16;
+
+/// Names accepted by `Airframe::preset`
+pub const PRESET_NAMES: [&str; 4] = ["quad-x", "hex-x", "octo-x", "plane"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AirframeError {
+    /// No preset has this name
+    UnknownPreset(String),
+    /// An output or control index is outside `0..NUM_CHANNELS`
+    InvalidChannel(usize),
+    /// Two outputs drive the same control
+    DuplicateControl(usize),
+    /// The airframe needs the mixer to spread attitude controls over its rotors
+    NeedsMixer(String),
+}
+
+impl fmt::Display for AirframeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AirframeError::UnknownPreset(s) => write!(f, "unknown airframe '{}', expected one of {:?}", s, PRESET_NAMES),
+            AirframeError::InvalidChannel(i) => write!(f, "channel {} is out of range", i),
+            AirframeError::DuplicateControl(i) => write!(f, "control {} is driven by more than one output", i),
+            AirframeError::NeedsMixer(s) => write!(f, "airframe '{}' needs the mixer to drive its rotors, so can't take actuator controls directly", s),
+        }
+    }
+}
+
+impl Error for AirframeError {}
+
+/// What an actuator output drives
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFunction {
+    /// Thrust, scaled to 0..1
+    Rotor,
+    /// Thrust from a reversible (3D mode) ESC, scaled to -1..1 around the trim PWM.
+    /// Outputs within `deadband` microseconds of trim give no thrust.
+    ReversibleRotor { deadband: f32 },
+    /// A control surface or other servo, scaled to -1..1
+    Surface,
+}
+
+/// Routes one `ActuatorOutputs` channel to one `ActuatorControls` channel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputMapping {
+    /// Index into `ActuatorOutputsData::output`
+    pub output: usize,
+    /// Index into the `ActuatorControls` passed to flighty
+    pub control: usize,
+    pub function: OutputFunction,
+}
+
+impl OutputMapping {
+    pub fn rotor(output: usize, control: usize) -> Self {
+        OutputMapping { output, control, function: OutputFunction::Rotor }
+    }
+
+    pub fn reversible_rotor(output: usize, control: usize, deadband: f32) -> Self {
+        OutputMapping { output, control, function: OutputFunction::ReversibleRotor { deadband } }
+    }
+
+    pub fn surface(output: usize, control: usize) -> Self {
+        OutputMapping { output, control, function: OutputFunction::Surface }
+    }
+}
+
+/// Describes how the mixer outputs of the mav firmware drive the simulated vehicle.
+///
+/// Controls that no output maps to are left at zero.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Airframe {
+    pub name: String,
+    mappings: Vec<OutputMapping>,
+    calibration: PwmCalibration,
+}
+
+impl Airframe {
+    pub fn new(name: &str, mappings: Vec<OutputMapping>) -> Result<Airframe, AirframeError> {
+        let mut used = [false; NUM_CHANNELS];
+        for mapping in mappings.iter() {
+            if mapping.output >= NUM_CHANNELS {
+                return Err(AirframeError::InvalidChannel(mapping.output));
+            }
+            if mapping.control >= NUM_CHANNELS {
+                return Err(AirframeError::InvalidChannel(mapping.control));
+            }
+            if used[mapping.control] {
+                return Err(AirframeError::DuplicateControl(mapping.control));
+            }
+            used[mapping.control] = true;
+        }
+        Ok(Airframe {
+            name: name.to_string(),
+            mappings,
+            calibration: PwmCalibration::default(),
+        })
+    }
+
+    /// Outputs `0..nrotors` drive rotors, and the remaining outputs surfaces, all in order
+    pub fn multirotor(name: &str, nrotors: usize) -> Airframe {
+        let nrotors = nrotors.min(NUM_CHANNELS);
+        let mappings = (0..NUM_CHANNELS)
+            .map(|i| {
+                if i < nrotors { OutputMapping::rotor(i, i) } else { OutputMapping::surface(i, i) }
+            })
+            .collect();
+        Airframe {
+            name: name.to_string(),
+            mappings,
+            calibration: PwmCalibration::default(),
+        }
+    }
+
+    pub fn quad_x() -> Airframe {
+        Airframe::multirotor("quad-x", 4)
+    }
+
+    pub fn hex_x() -> Airframe {
+        Airframe::multirotor("hex-x", 6)
+    }
+
+    pub fn octo_x() -> Airframe {
+        Airframe::multirotor("octo-x", 8)
+    }
+
+    /// Standard plane with the PX4 AERT output order: aileron, elevator, rudder, throttle.
+    /// Controls follow the PX4 attitude control group: roll, pitch, yaw, throttle.
+    pub fn standard_plane() -> Airframe {
+        Airframe {
+            name: "plane".to_string(),
+            mappings: vec![
+                OutputMapping::surface(0, 0),
+                OutputMapping::surface(1, 1),
+                OutputMapping::surface(2, 2),
+                OutputMapping::rotor(3, 3),
+            ],
+            calibration: PwmCalibration::default(),
+        }
+    }
+
+    /// Look up a preset by name, eg `"hex-x"`
+    pub fn preset(name: &str) -> Result<Airframe, AirframeError> {
+        match name {
+            "quad-x" => Ok(Airframe::quad_x()),
+            "hex-x" => Ok(Airframe::hex_x()),
+            "octo-x" => Ok(Airframe::octo_x()),
+            "plane" => Ok(Airframe::standard_plane()),
+            _ => Err(AirframeError::UnknownPreset(name.to_string())),
+        }
+    }
+
+    /// Replace the default 1000..2000us endpoints of every output
+    pub fn with_calibration(mut self, calibration: PwmCalibration) -> Airframe {
+        self.calibration = calibration;
+        self
+    }
+
+    /// Drive every rotor through a reversible ESC, eg for a 3D-capable vehicle
+    pub fn with_reversible_rotors(mut self, deadband: f32) -> Airframe {
+        for mapping in self.mappings.iter_mut() {
+            if OutputFunction::Rotor == mapping.function {
+                mapping.function = OutputFunction::ReversibleRotor { deadband };
+            }
+        }
+        self
+    }
+
+    pub fn mappings(&self) -> &[OutputMapping] {
+        &self.mappings
+    }
+
+    pub fn calibration(&self) -> &PwmCalibration {
+        &self.calibration
+    }
+
+    /// The controls that drive rotors, in mapping order
+    pub fn rotor_controls(&self) -> Vec<usize> {
+        self.mappings.iter()
+            .filter(|mapping| OutputFunction::Surface != mapping.function)
+            .map(|mapping| mapping.control)
+            .collect()
+    }
+
+    /// Convert PWM actuator outputs from the mav firmware into flighty controls
+    pub fn map_outputs(&self, pwm: &[f32; NUM_CHANNELS]) -> ActuatorControls {
+        let mut ctrls:ActuatorControls = [0.0; 16];
+        for mapping in self.mappings.iter() {
+            let pwm_val = pwm[mapping.output];
+            let channel = &self.calibration.channels[mapping.output];
+            if !channel.is_active(pwm_val) {
+                continue;
+            }
+            ctrls[mapping.control] = match mapping.function {
+                OutputFunction::Rotor => channel.unipolar(pwm_val),
+                OutputFunction::ReversibleRotor { deadband } => channel.bipolar_with_deadband(pwm_val, deadband),
+                OutputFunction::Surface => channel.bipolar(pwm_val),
+            };
+        }
+        ctrls
+    }
+}
+
+impl Default for Airframe {
+    fn default() -> Self {
+        Airframe::quad_x()
+    }
+}
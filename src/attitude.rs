@@ -0,0 +1,96 @@
+use flighty::physical_types::TimeBaseUnits;
+
+use crate::sim_clock::SimClock;
+
+/// A rotation quaternion in PX4 order: `[w, x, y, z]`
+pub type Quaternion = [f32; 4];
+
+pub const IDENTITY_QUATERNION: Quaternion = [1.0, 0.0, 0.0, 0.0];
+
+/// Hamilton product `a * b`: the rotation `b` followed by the rotation `a`
+pub fn quaternion_multiply(a: &Quaternion, b: &Quaternion) -> Quaternion {
+    [
+        a[0] * b[0] - a[1] * b[1] - a[2] * b[2] - a[3] * b[3],
+        a[0] * b[1] + a[1] * b[0] + a[2] * b[3] - a[3] * b[2],
+        a[0] * b[2] - a[1] * b[3] + a[2] * b[0] + a[3] * b[1],
+        a[0] * b[3] + a[1] * b[2] - a[2] * b[1] + a[3] * b[0],
+    ]
+}
+
+pub fn quaternion_conjugate(q: &Quaternion) -> Quaternion {
+    [q[0], -q[1], -q[2], -q[3]]
+}
+
+fn quaternion_normalize(q: &Quaternion) -> Quaternion {
+    let norm = q.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm <= std::f32::EPSILON {
+        return IDENTITY_QUATERNION;
+    }
+    [q[0] / norm, q[1] / norm, q[2] / norm, q[3] / norm]
+}
+
+/// Tracks the vehicle's attitude, the rotation from the FRD body frame to the NED earth frame,
+/// as simulated by flighty.
+///
+/// A discontinuous change of attitude is published as a reset, as the PX4 estimator does:
+/// `delta_q_reset` is the rotation applied, so that `q_new = delta_q_reset * q_old`,
+/// and `quat_reset_counter` increments.
+#[derive(Debug, Clone)]
+pub struct AttitudeTracker {
+    q: Quaternion,
+    clock: SimClock,
+    delta_q_reset: Quaternion,
+    reset_counter: u8,
+}
+
+impl Default for AttitudeTracker {
+    fn default() -> Self {
+        AttitudeTracker::new(IDENTITY_QUATERNION)
+    }
+}
+
+impl AttitudeTracker {
+    pub fn new(initial: Quaternion) -> Self {
+        AttitudeTracker {
+            q: quaternion_normalize(&initial),
+            clock: SimClock::default(),
+            delta_q_reset: IDENTITY_QUATERNION,
+            reset_counter: 0,
+        }
+    }
+
+    /// Advance to `time` (microseconds), where the simulated vehicle has `orientation`.
+    ///
+    /// Simulated time running backwards means the simulator restarted: the jump to
+    /// the new orientation is recorded as a reset.
+    pub fn update(&mut self, time: TimeBaseUnits, orientation: Quaternion) {
+        let (_dt, restarted) = self.clock.advance(time);
+        if restarted {
+            self.reset(orientation);
+        }
+        else {
+            self.q = quaternion_normalize(&orientation);
+        }
+    }
+
+    /// Jump to a new attitude, recording the change as a reset
+    pub fn reset(&mut self, q: Quaternion) {
+        let q = quaternion_normalize(&q);
+        self.delta_q_reset = quaternion_multiply(&q, &quaternion_conjugate(&self.q));
+        self.reset_counter = self.reset_counter.wrapping_add(1);
+        self.q = q;
+    }
+
+    pub fn quaternion(&self) -> Quaternion {
+        self.q
+    }
+
+    /// The rotation applied by the most recent reset, or the identity if there hasn't been one
+    pub fn delta_q_reset(&self) -> Quaternion {
+        self.delta_q_reset
+    }
+
+    pub fn reset_counter(&self) -> u8 {
+        self.reset_counter
+    }
+}
@@ -0,0 +1,254 @@
+use flighty::models::ActuatorControls;
+use flighty::physical_types::TimeBaseUnits;
+
+use uorb_codec::common::BatteryStatusData;
+
+use crate::airframe::Airframe;
+use crate::sim_clock::SimClock;
+
+/// Open circuit voltage of one LiPo cell against its state of charge (0..1)
+const LIPO_DISCHARGE_CURVE: [(f32, f32); 12] = [
+    (0.00, 3.27),
+    (0.05, 3.61),
+    (0.10, 3.69),
+    (0.20, 3.73),
+    (0.30, 3.77),
+    (0.40, 3.79),
+    (0.50, 3.82),
+    (0.60, 3.87),
+    (0.70, 3.92),
+    (0.80, 3.97),
+    (0.90, 4.06),
+    (1.00, 4.20),
+];
+
+/// Time constant of the filtered voltage and current, in seconds
+const FILTER_TIME_CONSTANT: f32 = 1.0;
+/// Time constant of the average current, in seconds
+const AVERAGE_TIME_CONSTANT: f32 = 30.0;
+
+/// Size and electrical characteristics of the simulated battery pack
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatteryConfig {
+    /// Number of cells in series, as per PX4 `BAT_N_CELLS`
+    pub cell_count: u8,
+    /// Full charge, in mAh, as per PX4 `BAT_CAPACITY`
+    pub capacity_mah: f32,
+    /// Internal resistance of each cell, in ohms: the cause of voltage sag under load
+    pub cell_resistance: f32,
+    /// Current drawn by the avionics with every rotor stopped, in amps
+    pub idle_current: f32,
+    /// Additional current drawn with every rotor at full thrust, in amps
+    pub max_load_current: f32,
+    /// Remaining fractions that trigger each warning, as per PX4 `BAT_LOW_THR`, `BAT_CRIT_THR` and `BAT_EMERGEN_THR`
+    pub low_threshold: f32,
+    pub critical_threshold: f32,
+    pub emergency_threshold: f32,
+}
+
+impl Default for BatteryConfig {
+    /// A 4S 12Ah pack
+    fn default() -> Self {
+        BatteryConfig {
+            cell_count: 4,
+            capacity_mah: 12000.0,
+            cell_resistance: 0.005,
+            idle_current: 0.5,
+            max_load_current: 60.0,
+            low_threshold: 0.15,
+            critical_threshold: 0.07,
+            emergency_threshold: 0.05,
+        }
+    }
+}
+
+/// Open circuit voltage of one cell at the given state of charge
+pub fn cell_open_circuit_voltage(charge: f32) -> f32 {
+    let charge = charge.max(0.0).min(1.0);
+    for pair in LIPO_DISCHARGE_CURVE.windows(2) {
+        let (lo_charge, lo_volts) = pair[0];
+        let (hi_charge, hi_volts) = pair[1];
+        if charge <= hi_charge {
+            return lo_volts + (hi_volts - lo_volts) * (charge - lo_charge) / (hi_charge - lo_charge);
+        }
+    }
+    LIPO_DISCHARGE_CURVE[LIPO_DISCHARGE_CURVE.len() - 1].1
+}
+
+/// Discharges a battery pack by the current drawn by the rotors,
+/// integrated over simulated time.
+///
+/// The load is taken from the controls that drive rotors: current rises with thrust
+/// to the power 1.5, as propeller power does. Reversible rotors draw current either way.
+#[derive(Debug, Clone)]
+pub struct BatteryModel {
+    config: BatteryConfig,
+    load_controls: Vec<usize>,
+    clock: SimClock,
+    discharged_mah: f32,
+    current: f32,
+    current_filtered: f32,
+    average_current: f32,
+    voltage: f32,
+    voltage_filtered: f32,
+    warning: u8,
+}
+
+impl Default for BatteryModel {
+    fn default() -> Self {
+        BatteryModel::new(BatteryConfig::default(), Airframe::default().rotor_controls())
+    }
+}
+
+impl BatteryModel {
+    /// A fully charged battery, loaded by the controls at `load_controls`
+    pub fn new(config: BatteryConfig, load_controls: Vec<usize>) -> Self {
+        let mut model = BatteryModel {
+            config,
+            load_controls,
+            clock: SimClock::default(),
+            discharged_mah: 0.0,
+            current: 0.0,
+            current_filtered: 0.0,
+            average_current: 0.0,
+            voltage: 0.0,
+            voltage_filtered: 0.0,
+            warning: BatteryStatusData::BATTERY_WARNING_NONE,
+        };
+        model.recharge();
+        model
+    }
+
+    /// Back to full charge, at rest
+    pub fn recharge(&mut self) {
+        self.discharged_mah = 0.0;
+        self.current = self.config.idle_current;
+        self.current_filtered = self.current;
+        self.average_current = self.current;
+        self.voltage = self.terminal_voltage(self.current);
+        self.voltage_filtered = self.voltage;
+        self.warning = BatteryStatusData::BATTERY_WARNING_NONE;
+    }
+
+    /// Advance to `time` (microseconds), having driven `controls` since the last update.
+    ///
+    /// Simulated time running backwards means the simulator restarted: the battery is recharged.
+    pub fn update(&mut self, time: TimeBaseUnits, controls: &ActuatorControls) {
+        let (dt, restarted) = self.clock.advance(time);
+        if restarted {
+            self.recharge();
+        }
+
+        self.current = self.config.idle_current + self.config.max_load_current * self.load(controls);
+        self.discharged_mah += self.current * dt / 3.6;
+        self.voltage = self.terminal_voltage(self.current);
+
+        let filter_gain = dt / (FILTER_TIME_CONSTANT + dt);
+        let average_gain = dt / (AVERAGE_TIME_CONSTANT + dt);
+        self.current_filtered += (self.current - self.current_filtered) * filter_gain;
+        self.voltage_filtered += (self.voltage - self.voltage_filtered) * filter_gain;
+        self.average_current += (self.current - self.average_current) * average_gain;
+
+        // as in PX4, a warning stays raised even if the voltage recovers
+        self.warning = self.warning.max(self.warning_for(self.remaining()));
+    }
+
+    /// Fraction (0..1) of full load, averaged over the load controls
+    fn load(&self, controls: &ActuatorControls) -> f32 {
+        if self.load_controls.is_empty() {
+            return 0.0;
+        }
+        let total: f32 = self.load_controls.iter()
+            .filter_map(|idx| controls.get(*idx))
+            .filter(|ctrl| ctrl.is_finite())
+            .map(|ctrl| ctrl.abs().min(1.0).powf(1.5))
+            .sum();
+        total / self.load_controls.len() as f32
+    }
+
+    /// Pack voltage while supplying `current`: open circuit voltage, less the internal resistance drop
+    fn terminal_voltage(&self, current: f32) -> f32 {
+        let cell_volts = cell_open_circuit_voltage(self.remaining()) - current * self.config.cell_resistance;
+        (cell_volts * self.config.cell_count as f32).max(0.0)
+    }
+
+    fn warning_for(&self, remaining: f32) -> u8 {
+        if remaining <= self.config.emergency_threshold {
+            BatteryStatusData::BATTERY_WARNING_EMERGENCY
+        }
+        else if remaining <= self.config.critical_threshold {
+            BatteryStatusData::BATTERY_WARNING_CRITICAL
+        }
+        else if remaining <= self.config.low_threshold {
+            BatteryStatusData::BATTERY_WARNING_LOW
+        }
+        else {
+            BatteryStatusData::BATTERY_WARNING_NONE
+        }
+    }
+
+    /// Minutes until empty, drawing `current` amps
+    fn minutes_to_empty(&self, current: f32) -> f32 {
+        let remaining_mah = (self.config.capacity_mah - self.discharged_mah).max(0.0);
+        if remaining_mah <= 0.0 {
+            return 0.0;
+        }
+        if current <= 0.0 {
+            return std::f32::INFINITY;
+        }
+        remaining_mah / (current * 1000.0) * 60.0
+    }
+
+    pub fn config(&self) -> &BatteryConfig {
+        &self.config
+    }
+
+    /// Fraction (0..1) of the capacity left
+    pub fn remaining(&self) -> f32 {
+        (1.0 - self.discharged_mah / self.config.capacity_mah).max(0.0).min(1.0)
+    }
+
+    pub fn discharged_mah(&self) -> f32 {
+        self.discharged_mah
+    }
+
+    pub fn current(&self) -> f32 {
+        self.current
+    }
+
+    pub fn current_filtered(&self) -> f32 {
+        self.current_filtered
+    }
+
+    pub fn average_current(&self) -> f32 {
+        self.average_current
+    }
+
+    pub fn voltage(&self) -> f32 {
+        self.voltage
+    }
+
+    pub fn voltage_filtered(&self) -> f32 {
+        self.voltage_filtered
+    }
+
+    /// Voltage of each cell, assuming a balanced pack
+    pub fn cell_voltage(&self) -> f32 {
+        self.voltage / self.config.cell_count as f32
+    }
+
+    /// Minutes until empty at the present current
+    pub fn run_time_to_empty(&self) -> f32 {
+        self.minutes_to_empty(self.current)
+    }
+
+    /// Minutes until empty at the average current
+    pub fn average_time_to_empty(&self) -> f32 {
+        self.minutes_to_empty(self.average_current)
+    }
+
+    /// One of the `BatteryStatusData::BATTERY_WARNING_*` levels
+    pub fn warning(&self) -> u8 {
+        self.warning
+    }
+}
@@ -0,0 +1,267 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::airframe::NUM_CHANNELS;
+
+/// Default minimum PWM in microseconds
+const PWM_DEFAULT_MIN: f32 = 1000.0;
+/// Default maximum PWM in microseconds
+const PWM_DEFAULT_MAX: f32 = 2000.0;
+const PWM_DEFAULT_CENTER: f32 = (PWM_DEFAULT_MAX + PWM_DEFAULT_MIN) / 2.0;
+/// Default PWM sent while disarmed, as per PX4 `PWM_DISARMED`
+const PWM_DEFAULT_DISARMED: f32 = 900.0;
+
+/// Outputs at or below this are switched off, regardless of calibration
+const PWM_OFF_THRESHOLD: f32 = PWM_DEFAULT_MIN / 2.0;
+
+/// How close an output must be to the disarmed value to count as disarmed
+const PWM_DISARMED_TOLERANCE: f32 = 0.5;
+
+#[derive(Debug)]
+pub enum CalibrationError {
+    /// A recognized parameter has a value that is not a number
+    InvalidParam(String),
+    /// A channel's endpoints are inconsistent, eg `min >= max` or trim outside `min..max`
+    InvalidChannel(usize),
+    /// The parameter file could not be read
+    Io(io::Error),
+}
+
+impl fmt::Display for CalibrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CalibrationError::InvalidParam(s) => write!(f, "invalid PWM parameter '{}'", s),
+            CalibrationError::InvalidChannel(i) => write!(f, "inconsistent PWM calibration for channel {}", i + 1),
+            CalibrationError::Io(e) => write!(f, "couldn't read PWM parameters: {}", e),
+        }
+    }
+}
+
+impl Error for CalibrationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CalibrationError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CalibrationError {
+    fn from(e: io::Error) -> Self {
+        CalibrationError::Io(e)
+    }
+}
+
+/// PWM endpoints of one actuator output, in microseconds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PwmChannel {
+    pub min: f32,
+    pub max: f32,
+    /// Neutral position of a surface, between `min` and `max`
+    pub trim: f32,
+    /// Value the firmware sends while disarmed: always maps to zero
+    pub disarmed: f32,
+    /// Mirror the output about the middle of `min..max`, as per PX4 `PWM_MAIN_REV`
+    pub reversed: bool,
+}
+
+impl Default for PwmChannel {
+    fn default() -> Self {
+        PwmChannel {
+            min: PWM_DEFAULT_MIN,
+            max: PWM_DEFAULT_MAX,
+            trim: PWM_DEFAULT_CENTER,
+            disarmed: PWM_DEFAULT_DISARMED,
+            reversed: false,
+        }
+    }
+}
+
+impl PwmChannel {
+    pub fn is_valid(&self) -> bool {
+        self.min < self.max && self.min <= self.trim && self.trim <= self.max
+    }
+
+    /// Whether the output is driving its actuator, rather than off or disarmed
+    pub fn is_active(&self, pwm: f32) -> bool {
+        !pwm.is_nan()
+            && pwm > PWM_OFF_THRESHOLD
+            && (pwm - self.disarmed).abs() >= PWM_DISARMED_TOLERANCE
+    }
+
+    /// Clamp to the endpoints and apply reversal
+    fn oriented(&self, pwm: f32) -> f32 {
+        let pwm = pwm.max(self.min).min(self.max);
+        if self.reversed { self.min + self.max - pwm } else { pwm }
+    }
+
+    /// Scale to 0..1 across `min..max`, eg for rotor thrust
+    pub fn unipolar(&self, pwm: f32) -> f32 {
+        if !self.is_valid() {
+            return 0.0;
+        }
+        (self.oriented(pwm) - self.min) / (self.max - self.min)
+    }
+
+    /// Scale to -1..1, with `trim` at zero and each side scaled to its own endpoint,
+    /// eg for surface deflection
+    pub fn bipolar(&self, pwm: f32) -> f32 {
+        if !self.is_valid() {
+            return 0.0;
+        }
+        let pwm = self.oriented(pwm);
+        if pwm > self.trim {
+            (pwm - self.trim) / (self.max - self.trim)
+        }
+        else if pwm < self.trim {
+            (pwm - self.trim) / (self.trim - self.min)
+        }
+        else {
+            0.0
+        }
+    }
+
+    /// Like `bipolar`, but outputs within `deadband` microseconds of `trim` map to zero,
+    /// and the remaining travel on each side is rescaled to still reach -1 and 1.
+    /// Suits reversible (3D mode) ESCs.
+    pub fn bipolar_with_deadband(&self, pwm: f32, deadband: f32) -> f32 {
+        if !self.is_valid() {
+            return 0.0;
+        }
+        let pwm = self.oriented(pwm);
+        let deadband = deadband.max(0.0);
+        let upper = self.trim + deadband;
+        let lower = self.trim - deadband;
+        if pwm > upper && upper < self.max {
+            (pwm - upper) / (self.max - upper)
+        }
+        else if pwm < lower && lower > self.min {
+            (pwm - lower) / (lower - self.min)
+        }
+        else {
+            0.0
+        }
+    }
+}
+
+/// PWM endpoints for every actuator output, indexed by output channel
+#[derive(Debug, Clone, PartialEq)]
+pub struct PwmCalibration {
+    pub channels: [PwmChannel; NUM_CHANNELS],
+}
+
+impl Default for PwmCalibration {
+    fn default() -> Self {
+        PwmCalibration {
+            channels: [PwmChannel::default(); NUM_CHANNELS],
+        }
+    }
+}
+
+/// Global PX4 parameters, old and new names, that apply to every channel without its own value
+const GLOBAL_MIN_PARAMS: [&str; 2] = ["PWM_MIN", "PWM_MAIN_MIN"];
+const GLOBAL_MAX_PARAMS: [&str; 2] = ["PWM_MAX", "PWM_MAIN_MAX"];
+const GLOBAL_DISARMED_PARAMS: [&str; 2] = ["PWM_DISARMED", "PWM_MAIN_DISARM"];
+
+/// Split a parameter line into name and value.
+///
+/// Accepts QGroundControl `.params` lines (`vehicle component NAME value type`)
+/// as well as plain `NAME value` lines. Blank lines and `#` comments yield `None`.
+fn parse_param_line(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    match fields.len() {
+        2 => Some((fields[0], fields[1])),
+        n if n >= 4 => Some((fields[2], fields[3])),
+        _ => None,
+    }
+}
+
+/// Split eg `PWM_MAIN_MIN3` into `("PWM_MAIN_MIN", 2)`: the zero-based channel index
+fn split_channel_param(name: &str) -> Option<(&str, usize)> {
+    let digits_at = name.find(|c: char| c.is_ascii_digit())?;
+    let channel: usize = name[digits_at..].parse().ok()?;
+    if !(1..=NUM_CHANNELS).contains(&channel) {
+        return None;
+    }
+    Some((&name[..digits_at], channel - 1))
+}
+
+impl PwmCalibration {
+    /// Read a PX4 parameter dump, or a hand-written file in the same format
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<PwmCalibration, CalibrationError> {
+        let text = fs::read_to_string(path)?;
+        PwmCalibration::from_params(&text)
+    }
+
+    /// Build a calibration from PX4 PWM parameters. Parameters that aren't PWM related are ignored.
+    ///
+    /// Understands the global `PWM_MAIN_MIN`, `PWM_MAIN_MAX` and `PWM_MAIN_DISARM`
+    /// (or their older `PWM_MIN`, `PWM_MAX` and `PWM_DISARMED` names),
+    /// the `PWM_MAIN_REV` bitmask, and the per-channel `PWM_MAIN_MINn`, `PWM_MAIN_MAXn`,
+    /// `PWM_MAIN_DISn`, `PWM_MAIN_TRIMn` and `PWM_MAIN_REVn`, where n counts from 1.
+    /// As in PX4, a per-channel endpoint of zero or less defers to the global value,
+    /// and trim is a fraction of the half range, -1..1 around the center.
+    pub fn from_params(text: &str) -> Result<PwmCalibration, CalibrationError> {
+        let mut global = PwmChannel::default();
+        let mut min = [None; NUM_CHANNELS];
+        let mut max = [None; NUM_CHANNELS];
+        let mut disarmed = [None; NUM_CHANNELS];
+        let mut trim = [0.0f32; NUM_CHANNELS];
+        let mut reversed = [false; NUM_CHANNELS];
+
+        for (name, value) in text.lines().filter_map(parse_param_line) {
+            if !name.starts_with("PWM_") {
+                continue;
+            }
+            let parse = || value.parse::<f32>()
+                .map_err(|_e| CalibrationError::InvalidParam(format!("{} {}", name, value)));
+
+            if GLOBAL_MIN_PARAMS.contains(&name) {
+                global.min = parse()?;
+            }
+            else if GLOBAL_MAX_PARAMS.contains(&name) {
+                global.max = parse()?;
+            }
+            else if GLOBAL_DISARMED_PARAMS.contains(&name) {
+                global.disarmed = parse()?;
+            }
+            else if name == "PWM_MAIN_REV" {
+                let mask = parse()? as u32;
+                for (i, rev) in reversed.iter_mut().enumerate() {
+                    *rev = *rev || (mask & (1 << i)) != 0;
+                }
+            }
+            else if let Some((prefix, i)) = split_channel_param(name) {
+                match prefix {
+                    "PWM_MAIN_MIN" => min[i] = Some(parse()?).filter(|v| *v > 0.0),
+                    "PWM_MAIN_MAX" => max[i] = Some(parse()?).filter(|v| *v > 0.0),
+                    "PWM_MAIN_DIS" => disarmed[i] = Some(parse()?).filter(|v| *v > 0.0),
+                    "PWM_MAIN_TRIM" => trim[i] = parse()?,
+                    "PWM_MAIN_REV" => reversed[i] = parse()? != 0.0,
+                    _ => {},
+                }
+            }
+        }
+
+        let mut calibration = PwmCalibration::default();
+        for (i, channel) in calibration.channels.iter_mut().enumerate() {
+            channel.min = min[i].unwrap_or(global.min);
+            channel.max = max[i].unwrap_or(global.max);
+            channel.disarmed = disarmed[i].unwrap_or(global.disarmed);
+            let center = (channel.min + channel.max) / 2.0;
+            channel.trim = center + trim[i] * (channel.max - channel.min) / 2.0;
+            channel.reversed = reversed[i];
+            if !channel.is_valid() {
+                return Err(CalibrationError::InvalidChannel(i));
+            }
+        }
+        Ok(calibration)
+    }
+}
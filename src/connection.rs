@@ -1,15 +1,32 @@
 
 
 
-use std::net::{TcpListener, TcpStream};
+use std::net::{TcpListener, TcpStream, Shutdown};
+use std::net::{UdpSocket, SocketAddr, Ipv4Addr, Ipv6Addr};
 use std::time::Duration;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex, RwLock, PoisonError};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender, TryRecvError};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
 use std::net::{ToSocketAddrs};
-use std::io::{self};
+use std::io::{self, Read, Write, Cursor};
+use std::str::FromStr;
+use std::fmt;
+use std::mem;
+use std::error::Error;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::path::Path;
 
 use uorb_codec::{UorbHeader, UorbMessage};
 use uorb_codec::read_msg;
 use uorb_codec::write_msg;
+use uorb_codec::UORB_MAGIC_V1;
+
+use serialport::{SerialPort, SerialPortSettings};
 
 pub trait UorbConnection {
 
@@ -18,83 +35,1524 @@ pub trait UorbConnection {
     /// Blocks until a valid frame is received, ignoring invalid messages.
     fn recv(&self) -> io::Result<(UorbHeader,UorbMessage)>;
 
+    /// Receive a message only if one is already available.
+    ///
+    /// Returns `Ok(None)` when nothing is waiting. Transports without a native
+    /// non-blocking path fall back to `recv`, so may block for up to their read timeout:
+    /// wrap them in a `PolledConnection` for a guaranteed non-blocking `try_recv`.
+    fn try_recv(&self) -> io::Result<Option<(UorbHeader,UorbMessage)>> {
+        match self.recv() {
+            Ok(msg) => Ok(Some(msg)),
+            Err(ref e) if is_timeout(e) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Total bytes discarded so far while resynchronizing to the next valid frame,
+    /// including whole datagrams that failed to decode.
+    ///
+    /// Transports that never discard input report zero.
+    fn skipped_bytes(&self) -> u64 {
+        0
+    }
+
     /// Send a  message
     fn send(&self, header: &UorbHeader, data: &UorbMessage) -> io::Result<()>;
+
+    /// Send several messages, in order.
+    ///
+    /// Stream transports encode the whole batch into one buffer and write it at once,
+    /// rather than issuing a small write per message.
+    fn send_batch(&self, msgs: &[(UorbHeader, UorbMessage)]) -> io::Result<()> {
+        for (header, data) in msgs {
+            self.send(header, data)?;
+        }
+        Ok(())
+    }
+}
+
+/// A boxed connection, such as one opened from a selector, is itself a connection
+impl<C: UorbConnection + ?Sized> UorbConnection for Box<C> {
+    fn recv(&self) -> io::Result<(UorbHeader, UorbMessage)> {
+        (**self).recv()
+    }
+
+    fn try_recv(&self) -> io::Result<Option<(UorbHeader, UorbMessage)>> {
+        (**self).try_recv()
+    }
+
+    fn skipped_bytes(&self) -> u64 {
+        (**self).skipped_bytes()
+    }
+
+    fn send(&self, header: &UorbHeader, data: &UorbMessage) -> io::Result<()> {
+        (**self).send(header, data)
+    }
+
+    fn send_batch(&self, msgs: &[(UorbHeader, UorbMessage)]) -> io::Result<()> {
+        (**self).send_batch(msgs)
+    }
+}
+
+/// Whether a receive error only means that no message arrived in time
+pub fn is_timeout(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
+}
+
+/// Encode a batch of messages, back to back, into `buf`
+pub fn encode_batch(buf: &mut Vec<u8>, msgs: &[(UorbHeader, UorbMessage)]) -> io::Result<()> {
+    for (header, data) in msgs {
+        write_msg(buf, header, data)?;
+    }
+    Ok(())
+}
+
+
+/// Errors raised while parsing a selector or opening a connection
+#[derive(Debug)]
+pub enum ConnectionError {
+    /// The selector has no `protocol:` prefix, or names an unknown protocol
+    UnknownProtocol(String),
+    /// The protocol is known, but not available on this platform
+    Unsupported(Protocol),
+    /// Only `tcpout` connections can reconnect
+    NotReconnectable(Protocol),
+    /// The host part of a `host:port` address is missing
+    InvalidHost(String),
+    /// The port part of a `host:port` address is missing or not a valid port number
+    InvalidPort(String),
+    /// The host could not be resolved to any socket address
+    AddressLookup(String),
+    /// A unix socket or serial device path is missing, or a unix socket path names an existing non-socket file
+    InvalidPath(String),
+    /// The serial baud rate is missing or not a number
+    InvalidBaudRate(String),
+    /// A `?key=value` selector option is unknown or has an invalid value
+    InvalidOption(String),
+    /// The address was valid, but opening the connection failed
+    Io(io::Error),
+}
+
+impl fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConnectionError::UnknownProtocol(s) => write!(f, "unknown protocol in selector '{}'", s),
+            ConnectionError::Unsupported(p) => write!(f, "protocol {:?} is not supported on this platform", p),
+            ConnectionError::NotReconnectable(p) => write!(f, "protocol {:?} cannot reconnect", p),
+            ConnectionError::InvalidHost(s) => write!(f, "missing or invalid host in address '{}'", s),
+            ConnectionError::InvalidPort(s) => write!(f, "missing or invalid port in address '{}'", s),
+            ConnectionError::AddressLookup(s) => write!(f, "could not resolve host '{}'", s),
+            ConnectionError::InvalidPath(s) => write!(f, "missing or invalid path in '{}'", s),
+            ConnectionError::InvalidBaudRate(s) => write!(f, "missing or invalid baud rate in '{}'", s),
+            ConnectionError::InvalidOption(s) => write!(f, "unknown or invalid connection option '{}'", s),
+            ConnectionError::Io(e) => write!(f, "connection failed: {}", e),
+        }
+    }
+}
+
+impl Error for ConnectionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConnectionError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ConnectionError {
+    fn from(e: io::Error) -> Self {
+        ConnectionError::Io(e)
+    }
+}
+
+impl From<ConnectionError> for io::Error {
+    fn from(e: ConnectionError) -> Self {
+        match e {
+            ConnectionError::Io(e) => e,
+            other => io::Error::new(io::ErrorKind::InvalidInput, other.to_string()),
+        }
+    }
+}
+
+/// A poisoned lock means another thread panicked mid-frame: report it rather than panic again
+pub(crate) fn poisoned<T>(_e: PoisonError<T>) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "connection lock poisoned")
+}
+
+/// Transport protocols understood by `select_protocol`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    TcpOut,
+    TcpIn,
+    TcpServer,
+    UdpIn,
+    UdpOut,
+    Serial,
+    Unix,
+    UnixIn,
+}
+
+/// Split a selector such as `tcpout:127.0.0.1:4560` into its protocol and address parts
+pub fn parse_selector(selector: &str) -> Result<(Protocol, &str), ConnectionError> {
+    let idx = selector.find(':')
+        .ok_or_else(|| ConnectionError::UnknownProtocol(selector.to_string()))?;
+    let protocol = match &selector[..idx] {
+        "tcpout" => Protocol::TcpOut,
+        "tcpin" => Protocol::TcpIn,
+        "tcpserver" => Protocol::TcpServer,
+        "udpin" => Protocol::UdpIn,
+        "udpout" => Protocol::UdpOut,
+        "serial" => Protocol::Serial,
+        "unix" => Protocol::Unix,
+        "unixin" => Protocol::UnixIn,
+        _ => return Err(ConnectionError::UnknownProtocol(selector.to_string())),
+    };
+    Ok((protocol, &selector[idx + 1..]))
+}
+
+/// Parse and resolve a `host:port` address, eg `127.0.0.1:4560` or `[::1]:4560`
+pub fn parse_socket_addr(address: &str) -> Result<SocketAddr, ConnectionError> {
+    let idx = address.rfind(':')
+        .ok_or_else(|| ConnectionError::InvalidPort(address.to_string()))?;
+    let host = address[..idx].trim_start_matches('[').trim_end_matches(']');
+    let port = &address[idx + 1..];
+    if host.is_empty() {
+        return Err(ConnectionError::InvalidHost(address.to_string()));
+    }
+    let port = port.parse::<u16>()
+        .map_err(|_| ConnectionError::InvalidPort(address.to_string()))?;
+    (host, port).to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or_else(|| ConnectionError::AddressLookup(host.to_string()))
+}
+
+/// Parse a serial address such as `/dev/ttyUSB0:921600` into device path and baud rate
+pub fn parse_serial_settings(settings: &str) -> Result<(&str, u32), ConnectionError> {
+    let idx = settings.rfind(':')
+        .ok_or_else(|| ConnectionError::InvalidBaudRate(settings.to_string()))?;
+    let port_name = &settings[..idx];
+    if port_name.is_empty() {
+        return Err(ConnectionError::InvalidPath(settings.to_string()));
+    }
+    let baud_rate = settings[idx + 1..].parse::<u32>()
+        .map_err(|_| ConnectionError::InvalidBaudRate(settings.to_string()))?;
+    Ok((port_name, baud_rate))
+}
+
+fn resolve_addr<T: ToSocketAddrs>(address: T) -> Result<SocketAddr, ConnectionError> {
+    match address.to_socket_addrs() {
+        Ok(mut addrs) => addrs.next()
+            .ok_or_else(|| ConnectionError::AddressLookup("no addresses found".to_string())),
+        Err(e) => Err(ConnectionError::AddressLookup(e.to_string())),
+    }
+}
+
+
+/// Socket options that can be carried in a selector, eg
+/// `tcpout:127.0.0.1:4560?read_timeout_ms=5&nodelay=true&sndbuf=65536`
+///
+/// Options that do not apply to a transport (eg `nodelay` on UDP) are ignored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionOptions {
+    /// `read_timeout_ms`: how long `recv` may block; `None` (or `0` in a selector) blocks indefinitely
+    pub read_timeout: Option<Duration>,
+    /// `write_timeout_ms`: how long `send` may block; `None` blocks indefinitely
+    pub write_timeout: Option<Duration>,
+    /// `nodelay`: disable Nagle's algorithm on TCP sockets
+    pub nodelay: bool,
+    /// `sndbuf`: socket send buffer size in bytes
+    pub send_buffer_size: Option<usize>,
+    /// `rcvbuf`: socket receive buffer size in bytes
+    pub recv_buffer_size: Option<usize>,
+}
+
+/// Default read timeout, short enough that reader loops stay responsive
+const DEFAULT_READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        ConnectionOptions {
+            read_timeout: Some(DEFAULT_READ_TIMEOUT),
+            write_timeout: None,
+            nodelay: false,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+        }
+    }
+}
+
+impl ConnectionOptions {
+    /// The options each protocol has historically used when none are given
+    pub fn default_for(protocol: Protocol) -> Self {
+        match protocol {
+            // tcpin has always blocked on reads
+            Protocol::TcpIn => ConnectionOptions {
+                read_timeout: None,
+                ..Default::default()
+            },
+            _ => Default::default(),
+        }
+    }
+
+    fn parse_query(&mut self, query: &str) -> Result<(), ConnectionError> {
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let invalid = || ConnectionError::InvalidOption(pair.to_string());
+            let mut kv = pair.splitn(2, '=');
+            let key = kv.next().unwrap_or("");
+            let value = kv.next().ok_or_else(invalid)?;
+            match key {
+                "read_timeout_ms" => {
+                    let millis = value.parse::<u64>().map_err(|_| invalid())?;
+                    self.read_timeout = if 0 == millis { None } else { Some(Duration::from_millis(millis)) };
+                },
+                "write_timeout_ms" => {
+                    let millis = value.parse::<u64>().map_err(|_| invalid())?;
+                    self.write_timeout = if 0 == millis { None } else { Some(Duration::from_millis(millis)) };
+                },
+                "nodelay" => {
+                    self.nodelay = value.parse::<bool>().map_err(|_| invalid())?;
+                },
+                "sndbuf" => {
+                    self.send_buffer_size = Some(value.parse::<usize>().map_err(|_| invalid())?);
+                },
+                "rcvbuf" => {
+                    self.recv_buffer_size = Some(value.parse::<usize>().map_err(|_| invalid())?);
+                },
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A typed connection selector: which protocol, which address, and socket options.
+///
+/// Parse one from a selector string, or build one directly:
+///
+/// ```no_run
+/// use mavulator::connection::{ConnectionSpec, Protocol};
+///
+/// let mut spec = ConnectionSpec::new(Protocol::TcpOut, "127.0.0.1:4560");
+/// spec.options.nodelay = true;
+/// let conn = spec.open().unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionSpec {
+    pub protocol: Protocol,
+    /// `host:port` for network protocols, a path for unix sockets, `<port>:<baud>` for serial
+    pub address: String,
+    pub options: ConnectionOptions,
+}
+
+impl ConnectionSpec {
+    pub fn new(protocol: Protocol, address: &str) -> Self {
+        ConnectionSpec {
+            protocol,
+            address: address.to_string(),
+            options: ConnectionOptions::default_for(protocol),
+        }
+    }
+
+    /// Parse a selector such as `tcpout:127.0.0.1:4560?read_timeout_ms=5&nodelay=true`
+    pub fn parse(selector: &str) -> Result<Self, ConnectionError> {
+        let (base, query) = match selector.find('?') {
+            Some(idx) => (&selector[..idx], &selector[idx + 1..]),
+            None => (selector, ""),
+        };
+        let (protocol, address) = parse_selector(base)?;
+        let mut spec = ConnectionSpec::new(protocol, address);
+        spec.options.parse_query(query)?;
+        Ok(spec)
+    }
+
+    /// Open the connection this spec describes
+    pub fn open(&self) -> Result<Box<dyn UorbConnection + Sync + Send>, ConnectionError> {
+        let address = &self.address[..];
+        let options = &self.options;
+        match self.protocol {
+            Protocol::TcpOut => Ok(Box::new(tcpout_with(parse_socket_addr(address)?, options)?)),
+            Protocol::TcpIn => Ok(Box::new(tcpin_with(parse_socket_addr(address)?, options)?)),
+            Protocol::TcpServer => Ok(Box::new(tcpserver_with(parse_socket_addr(address)?, options)?)),
+            Protocol::UdpIn => Ok(Box::new(udpin_with(parse_socket_addr(address)?, options)?)),
+            Protocol::UdpOut => Ok(Box::new(udpout_with(parse_socket_addr(address)?, options)?)),
+            Protocol::Serial => Ok(Box::new(open_serial_with(address, options)?)),
+            #[cfg(unix)]
+            Protocol::Unix => Ok(Box::new(unix_with(unix_path(address)?, options)?)),
+            #[cfg(unix)]
+            Protocol::UnixIn => Ok(Box::new(unixin_with(unix_path(address)?, options)?)),
+            #[cfg(not(unix))]
+            Protocol::Unix | Protocol::UnixIn => Err(ConnectionError::Unsupported(self.protocol)),
+        }
+    }
+
+    /// Open a `tcpout` spec as a connection that reconnects with the given backoff policy
+    pub fn open_reconnecting(&self, backoff: Backoff) -> Result<ReconnectingTcpConnection, ConnectionError> {
+        if self.protocol != Protocol::TcpOut {
+            return Err(ConnectionError::NotReconnectable(self.protocol));
+        }
+        tcpout_reconnecting_with(parse_socket_addr(&self.address)?, backoff, &self.options)
+    }
+}
+
+impl FromStr for ConnectionSpec {
+    type Err = ConnectionError;
+
+    fn from_str(selector: &str) -> Result<Self, Self::Err> {
+        ConnectionSpec::parse(selector)
+    }
 }
 
 
 /// TCP  connection
 
-pub fn select_protocol(address: &str) -> io::Result<Box<UorbConnection + Sync + Send>> {
-    if address.starts_with("tcpout:")  {
-        Ok(Box::new(tcpout(&address["tcpout:".len()..])?))
-    } else if address.starts_with("tcpin:") {
-        Ok(Box::new(tcpin(&address["tcpin:".len()..])?))
+pub fn select_protocol(selector: &str) -> Result<Box<dyn UorbConnection + Sync + Send>, ConnectionError> {
+    ConnectionSpec::parse(selector)?.open()
+}
+
+/// Apply options to a connected TCP stream
+fn configure_tcp(socket: TcpStream, options: &ConnectionOptions) -> io::Result<TcpStream> {
+    socket.set_read_timeout(options.read_timeout)?;
+    socket.set_write_timeout(options.write_timeout)?;
+    socket.set_nodelay(options.nodelay)?;
+    if options.send_buffer_size.is_none() && options.recv_buffer_size.is_none() {
+        return Ok(socket);
+    }
+    let socket = socket2::Socket::from(socket);
+    if let Some(size) = options.send_buffer_size {
+        socket.set_send_buffer_size(size)?;
     }
-    else {
-        Err(io::Error::new(io::ErrorKind::AddrNotAvailable,"Protocol unsupported"))
+    if let Some(size) = options.recv_buffer_size {
+        socket.set_recv_buffer_size(size)?;
     }
+    Ok(socket.into_tcp_stream())
 }
 
-pub fn tcpout<T: ToSocketAddrs>(address: T) -> io::Result<TcpConnection> {
-    let addr = address.to_socket_addrs().unwrap().next().expect("Host address lookup failed.");
-    let socket = TcpStream::connect(&addr)?;
-    socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+pub fn tcpout<T: ToSocketAddrs>(address: T) -> Result<TcpConnection, ConnectionError> {
+    tcpout_with(address, &ConnectionOptions::default_for(Protocol::TcpOut))
+}
 
-    Ok(TcpConnection {
-        reader: Mutex::new(socket.try_clone()?),
-        writer: Mutex::new(TcpWrite {
-            socket: socket,
-        }),
-    })
+fn tcpout_with<T: ToSocketAddrs>(address: T, options: &ConnectionOptions) -> Result<TcpConnection, ConnectionError> {
+    let addr = resolve_addr(address)?;
+    Ok(tcp_connect(&addr, options, Arc::new(AtomicU64::new(0)))?)
 }
 
-pub fn tcpin<T: ToSocketAddrs>(address: T) -> io::Result<TcpConnection> {
-    let addr = address.to_socket_addrs().unwrap().next().expect("Invalid address");
+fn tcp_connect(addr: &SocketAddr, options: &ConnectionOptions, skipped: Arc<AtomicU64>) -> io::Result<TcpConnection> {
+    let socket = configure_tcp(TcpStream::connect(addr)?, options)?;
+    TcpConnection::new(socket, skipped)
+}
+
+pub fn tcpin<T: ToSocketAddrs>(address: T) -> Result<TcpConnection, ConnectionError> {
+    tcpin_with(address, &ConnectionOptions::default_for(Protocol::TcpIn))
+}
+
+fn tcpin_with<T: ToSocketAddrs>(address: T, options: &ConnectionOptions) -> Result<TcpConnection, ConnectionError> {
+    let addr = resolve_addr(address)?;
     let listener = TcpListener::bind(&addr)?;
 
     //For now we only accept one incoming stream: this blocks until we get one
     for incoming in listener.incoming() {
         match incoming {
             Ok(socket) => {
-                return Ok(TcpConnection {
-                    reader: Mutex::new(socket.try_clone()?),
-                    writer: Mutex::new(TcpWrite {
-                        socket: socket,
-                    }),
-                })
+                let socket = configure_tcp(socket, options)?;
+                return Ok(TcpConnection::new(socket, Arc::new(AtomicU64::new(0)))?)
             },
             Err(e) => {
                 //println!("listener err: {}", e);
-                return Err(e);
+                return Err(e.into());
             },
         }
     }
     Err(io::Error::new(
         io::ErrorKind::NotConnected,
         "No incoming connections!",
-    ))
+    ).into())
 }
 
 pub struct TcpConnection {
-    reader: Mutex<TcpStream>,
+    reader: Mutex<ResyncReader<TcpStream>>,
     writer: Mutex<TcpWrite>,
+    skipped: Arc<AtomicU64>,
 }
 
 struct TcpWrite {
     socket: TcpStream,
+    /// Reused between batches to avoid reallocating
+    send_buf: Vec<u8>,
 }
 
+impl TcpConnection {
+    /// `skipped` accumulates the bytes discarded by the reader, and may be shared between links
+    fn new(socket: TcpStream, skipped: Arc<AtomicU64>) -> io::Result<TcpConnection> {
+        Ok(TcpConnection {
+            reader: Mutex::new(ResyncReader::new(socket.try_clone()?, skipped.clone())),
+            writer: Mutex::new(TcpWrite {
+                socket: socket,
+                send_buf: Vec::new(),
+            }),
+            skipped,
+        })
+    }
+}
 
 impl UorbConnection for TcpConnection {
     fn recv(&self) -> io::Result<(UorbHeader, UorbMessage)> {
-        let mut lock = self.reader.lock().expect("tcp read failure");
-        read_msg(&mut *lock)
+        let mut lock = self.reader.lock().map_err(poisoned)?;
+        lock.recv()
+    }
+
+    fn skipped_bytes(&self) -> u64 {
+        self.skipped.load(Ordering::SeqCst)
     }
 
     fn send(&self, header: &UorbHeader, data: &UorbMessage) -> io::Result<()> {
-        let mut lock = self.writer.lock().unwrap();
+        let mut lock = self.writer.lock().map_err(poisoned)?;
         write_msg(&mut lock.socket, header, data)
     }
 
+    fn send_batch(&self, msgs: &[(UorbHeader, UorbMessage)]) -> io::Result<()> {
+        let mut lock = self.writer.lock().map_err(poisoned)?;
+        let state = &mut *lock;
+        state.send_buf.clear();
+        encode_batch(&mut state.send_buf, msgs)?;
+        state.socket.write_all(&state.send_buf)
+    }
+
+}
+
+
+/// Exponential backoff policy used when re-establishing a lost connection
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    /// Delay after the first failed attempt
+    pub initial_delay: Duration,
+    /// Upper bound on the delay between attempts
+    pub max_delay: Duration,
+    /// Factor applied to the delay after each failed attempt
+    pub multiplier: u32,
+    /// Give up after this many attempts; `None` retries forever
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(5),
+            multiplier: 2,
+            max_attempts: None,
+        }
+    }
+}
+
+/// Connection state transitions reported by `ReconnectingTcpConnection`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConnectionState {
+    /// The link failed and reconnection is about to start
+    Disconnected,
+    /// A (re)connection attempt is in progress
+    Reconnecting { attempt: u32 },
+    /// The link is up and the handshake has been sent
+    Connected,
+    /// `Backoff::max_attempts` was exhausted
+    GaveUp,
+}
+
+type HandshakeFn = dyn Fn(&dyn UorbConnection) -> io::Result<()> + Send + Sync;
+type StateListenerFn = dyn Fn(ConnectionState) + Send + Sync;
+
+struct TcpLink {
+    /// Incremented on every successful reconnect,
+    /// so that concurrent failures on the same link only reconnect once
+    generation: u64,
+    conn: Arc<TcpConnection>,
+}
+
+/// TCP client connection that transparently reconnects when the peer goes away,
+/// eg when a px4_sitl sidecar is restarted.
+///
+/// `send` and `recv` block while reconnecting, and only return an error
+/// once the backoff policy gives up.
+pub struct ReconnectingTcpConnection {
+    addr: SocketAddr,
+    backoff: Backoff,
+    options: ConnectionOptions,
+    link: RwLock<TcpLink>,
+    reconnect_lock: Mutex<()>,
+    on_connect: Option<Box<HandshakeFn>>,
+    state_listener: Option<Box<StateListenerFn>>,
+    /// Shared by every link, so the count survives reconnects
+    skipped: Arc<AtomicU64>,
+}
+
+pub fn tcpout_reconnecting<T: ToSocketAddrs>(address: T, backoff: Backoff) -> Result<ReconnectingTcpConnection, ConnectionError> {
+    tcpout_reconnecting_with(address, backoff, &ConnectionOptions::default_for(Protocol::TcpOut))
+}
+
+fn tcpout_reconnecting_with<T: ToSocketAddrs>(address: T,
+                                              backoff: Backoff,
+                                              options: &ConnectionOptions
+) -> Result<ReconnectingTcpConnection, ConnectionError> {
+    let addr = resolve_addr(address)?;
+    let skipped = Arc::new(AtomicU64::new(0));
+    let conn = tcp_connect(&addr, options, skipped.clone())?;
+
+    Ok(ReconnectingTcpConnection {
+        addr,
+        backoff,
+        options: options.clone(),
+        link: RwLock::new(TcpLink {
+            generation: 0,
+            conn: Arc::new(conn),
+        }),
+        reconnect_lock: Mutex::new(()),
+        on_connect: None,
+        state_listener: None,
+        skipped,
+    })
+}
+
+impl ReconnectingTcpConnection {
+
+    /// Set the handshake sent on every new link, before any other traffic
+    pub fn set_on_connect<F>(&mut self, handshake: F)
+        where F: Fn(&dyn UorbConnection) -> io::Result<()> + Send + Sync + 'static {
+        self.on_connect = Some(Box::new(handshake));
+    }
+
+    /// Set a callback that is notified of every connection state transition
+    pub fn set_state_listener<F>(&mut self, listener: F)
+        where F: Fn(ConnectionState) + Send + Sync + 'static {
+        self.state_listener = Some(Box::new(listener));
+    }
+
+    fn report(&self, state: ConnectionState) {
+        if let Some(ref listener) = self.state_listener {
+            listener(state);
+        }
+    }
+
+    fn current(&self) -> io::Result<(u64, Arc<TcpConnection>)> {
+        let link = self.link.read().map_err(poisoned)?;
+        Ok((link.generation, link.conn.clone()))
+    }
+
+    fn connect_with_handshake(&self) -> io::Result<TcpConnection> {
+        let conn = tcp_connect(&self.addr, &self.options, self.skipped.clone())?;
+        if let Some(ref handshake) = self.on_connect {
+            handshake(&conn)?;
+        }
+        Ok(conn)
+    }
+
+    /// Replace the link that failed at `failed_generation`, retrying per the backoff policy
+    fn reconnect(&self, failed_generation: u64) -> io::Result<()> {
+        let _guard = self.reconnect_lock.lock().map_err(poisoned)?;
+        if self.link.read().map_err(poisoned)?.generation != failed_generation {
+            // another thread already replaced the failed link
+            return Ok(());
+        }
+
+        self.report(ConnectionState::Disconnected);
+        let mut delay = self.backoff.initial_delay;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            self.report(ConnectionState::Reconnecting { attempt });
+            match self.connect_with_handshake() {
+                Ok(conn) => {
+                    {
+                        let mut link = self.link.write().map_err(poisoned)?;
+                        link.generation += 1;
+                        link.conn = Arc::new(conn);
+                    }
+                    self.report(ConnectionState::Connected);
+                    return Ok(());
+                },
+                Err(e) => {
+                    if let Some(max_attempts) = self.backoff.max_attempts {
+                        if attempt >= max_attempts {
+                            self.report(ConnectionState::GaveUp);
+                            return Err(e);
+                        }
+                    }
+                    thread::sleep(delay);
+                    delay = std::cmp::min(delay * self.backoff.multiplier, self.backoff.max_delay);
+                },
+            }
+        }
+    }
+}
+
+impl UorbConnection for ReconnectingTcpConnection {
+    fn recv(&self) -> io::Result<(UorbHeader, UorbMessage)> {
+        loop {
+            let (generation, conn) = self.current()?;
+            match conn.recv() {
+                Err(ref e) if !is_timeout(e) => {
+                    self.reconnect(generation)?;
+                },
+                res => return res,
+            }
+        }
+    }
+
+    fn send(&self, header: &UorbHeader, data: &UorbMessage) -> io::Result<()> {
+        let (generation, conn) = self.current()?;
+        if conn.send(header, data).is_ok() {
+            return Ok(());
+        }
+        self.reconnect(generation)?;
+        let (_generation, conn) = self.current()?;
+        conn.send(header, data)
+    }
+
+    fn send_batch(&self, msgs: &[(UorbHeader, UorbMessage)]) -> io::Result<()> {
+        let (generation, conn) = self.current()?;
+        if conn.send_batch(msgs).is_ok() {
+            return Ok(());
+        }
+        self.reconnect(generation)?;
+        let (_generation, conn) = self.current()?;
+        conn.send_batch(msgs)
+    }
+
+    fn skipped_bytes(&self) -> u64 {
+        self.skipped.load(Ordering::SeqCst)
+    }
+}
+
+
+/// Multi-client TCP server connection
+///
+/// Keeps accepting clients for as long as it lives: dropping it stops accepting and disconnects every client.
+/// Every message passed to `send` is broadcast to all connected clients,
+/// and `recv` yields messages from any client in arrival order.
+/// A client that fails a read or write is dropped without disturbing the others.
+
+pub fn tcpserver<T: ToSocketAddrs>(address: T) -> Result<TcpServerConnection, ConnectionError> {
+    tcpserver_with(address, &ConnectionOptions::default_for(Protocol::TcpServer))
+}
+
+fn tcpserver_with<T: ToSocketAddrs>(address: T, options: &ConnectionOptions) -> Result<TcpServerConnection, ConnectionError> {
+    let addr = resolve_addr(address)?;
+    let listener = TcpListener::bind(&addr)?;
+    let local_addr = listener.local_addr()?;
+    let clients = Arc::new(Mutex::new(Vec::new()));
+    let (inbound_tx, inbound_rx) = mpsc::channel();
+    let shutdown = Arc::new(AtomicBool::new(false));
+
+    // each client has a dedicated reader thread, so reads may block indefinitely,
+    // but a stalled client must not hold up broadcasts for long
+    let client_options = ConnectionOptions {
+        read_timeout: None,
+        write_timeout: options.write_timeout.or(Some(TCP_SERVER_WRITE_TIMEOUT)),
+        ..options.clone()
+    };
+
+    let skipped = Arc::new(AtomicU64::new(0));
+
+    thread::spawn({
+        let clients = clients.clone();
+        let skipped = skipped.clone();
+        let shutdown = shutdown.clone();
+        move || {
+            tcpserver_accept_loop(listener, clients, inbound_tx, client_options, skipped, shutdown);
+        }
+    });
+
+    Ok(TcpServerConnection {
+        clients,
+        inbound: Mutex::new(inbound_rx),
+        skipped,
+        local_addr,
+        shutdown,
+    })
+}
+
+/// How long a broadcast may block on one slow client before that client is dropped
+const TCP_SERVER_WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+struct TcpServerClient {
+    socket: TcpStream,
+    /// Cleared by the client's reader thread when its stream fails
+    alive: Arc<AtomicBool>,
+}
+
+fn tcpserver_accept_loop(listener: TcpListener,
+                         clients: Arc<Mutex<Vec<TcpServerClient>>>,
+                         inbound: Sender<(UorbHeader, UorbMessage)>,
+                         client_options: ConnectionOptions,
+                         skipped: Arc<AtomicU64>,
+                         shutdown: Arc<AtomicBool>) {
+    for incoming in listener.incoming() {
+        if shutdown.load(Ordering::SeqCst) {
+            // woken by the server connection being dropped: this connection is ours, not a client
+            break;
+        }
+        let socket = match incoming.and_then(|socket| configure_tcp(socket, &client_options)) {
+            Ok(socket) => socket,
+            Err(_e) => continue,
+        };
+        let mut reader = match socket.try_clone() {
+            Ok(reader) => ResyncReader::new(reader, skipped.clone()),
+            Err(_e) => continue,
+        };
+        let alive = Arc::new(AtomicBool::new(true));
+
+        thread::spawn({
+            let alive = alive.clone();
+            let inbound = inbound.clone();
+            move || {
+                loop {
+                    match reader.recv() {
+                        Ok(msg) => {
+                            if inbound.send(msg).is_err() {
+                                // the server connection has been dropped
+                                break;
+                            }
+                        },
+                        Err(_e) => break,
+                    }
+                }
+                alive.store(false, Ordering::SeqCst);
+            }
+        });
+
+        match clients.lock() {
+            Ok(mut clients) => clients.push(TcpServerClient {
+                socket,
+                alive,
+            }),
+            Err(_e) => break,
+        }
+    }
+}
+
+pub struct TcpServerConnection {
+    clients: Arc<Mutex<Vec<TcpServerClient>>>,
+    inbound: Mutex<Receiver<(UorbHeader, UorbMessage)>>,
+    /// Summed over all clients
+    skipped: Arc<AtomicU64>,
+    local_addr: SocketAddr,
+    /// Tells the accept loop to stop
+    shutdown: Arc<AtomicBool>,
+}
+
+impl TcpServerConnection {
+    /// The address the server is listening on, eg to find the port chosen when binding port 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Write already-encoded frames to every live client
+    fn broadcast(&self, frames: &[u8]) -> io::Result<()> {
+        let mut clients = self.clients.lock().map_err(poisoned)?;
+        clients.retain(|client| client.alive.load(Ordering::SeqCst));
+        for client in clients.iter_mut() {
+            if client.socket.write_all(frames).is_err() {
+                // a partial write leaves the stream unusable: close it so the reader exits too
+                let _ = client.socket.shutdown(Shutdown::Both);
+                client.alive.store(false, Ordering::SeqCst);
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of clients currently connected
+    pub fn client_count(&self) -> usize {
+        match self.clients.lock() {
+            Ok(clients) => clients.iter().filter(|c| c.alive.load(Ordering::SeqCst)).count(),
+            Err(_e) => 0,
+        }
+    }
+}
+
+impl Drop for TcpServerConnection {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // wake the accept loop, which is blocked until a client connects
+        let mut wake_addr = self.local_addr;
+        if wake_addr.ip().is_unspecified() {
+            wake_addr.set_ip(match wake_addr {
+                SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+        let _ = TcpStream::connect_timeout(&wake_addr, TCP_SERVER_WRITE_TIMEOUT);
+
+        // closing each client stream ends its reader thread
+        if let Ok(clients) = self.clients.lock() {
+            for client in clients.iter() {
+                let _ = client.socket.shutdown(Shutdown::Both);
+            }
+        }
+    }
+}
+
+impl UorbConnection for TcpServerConnection {
+    fn recv(&self) -> io::Result<(UorbHeader, UorbMessage)> {
+        let lock = self.inbound.lock().map_err(poisoned)?;
+        match lock.recv_timeout(Duration::from_millis(100)) {
+            Ok(msg) => Ok(msg),
+            Err(RecvTimeoutError::Timeout) => Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "No messages from any client",
+            )),
+            Err(RecvTimeoutError::Disconnected) => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "Server listener stopped",
+            )),
+        }
+    }
+
+    fn try_recv(&self) -> io::Result<Option<(UorbHeader, UorbMessage)>> {
+        let lock = self.inbound.lock().map_err(poisoned)?;
+        match lock.try_recv() {
+            Ok(msg) => Ok(Some(msg)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "Server listener stopped",
+            )),
+        }
+    }
+
+    fn skipped_bytes(&self) -> u64 {
+        self.skipped.load(Ordering::SeqCst)
+    }
+
+    /// Broadcast to every client. Succeeds even when no clients are connected.
+    fn send(&self, header: &UorbHeader, data: &UorbMessage) -> io::Result<()> {
+        let mut buf = Vec::new();
+        write_msg(&mut buf, header, data)?;
+        self.broadcast(&buf)
+    }
+
+    fn send_batch(&self, msgs: &[(UorbHeader, UorbMessage)]) -> io::Result<()> {
+        let mut buf = Vec::new();
+        encode_batch(&mut buf, msgs)?;
+        self.broadcast(&buf)
+    }
+}
+
+
+/// In-memory connection pair
+///
+/// Returns two linked endpoints: whatever is sent on one is received on the other.
+/// With `capacity` set, `send` blocks once that many messages are waiting to be received;
+/// otherwise the queue is unbounded.
+/// Useful for running firmware stand-ins and the simulator in one process, eg in tests.
+
+pub fn pair(capacity: Option<usize>) -> (ChannelConnection, ChannelConnection) {
+    let (a_tx, b_rx) = channel_sender(capacity);
+    let (b_tx, a_rx) = channel_sender(capacity);
+    (ChannelConnection::new(a_tx, a_rx), ChannelConnection::new(b_tx, b_rx))
+}
+
+fn channel_sender(capacity: Option<usize>) -> (ChannelSender, Receiver<(UorbHeader, UorbMessage)>) {
+    match capacity {
+        Some(bound) => {
+            let (tx, rx) = mpsc::sync_channel(bound);
+            (ChannelSender::Bounded(tx), rx)
+        },
+        None => {
+            let (tx, rx) = mpsc::channel();
+            (ChannelSender::Unbounded(tx), rx)
+        },
+    }
+}
+
+enum ChannelSender {
+    Unbounded(Sender<(UorbHeader, UorbMessage)>),
+    Bounded(SyncSender<(UorbHeader, UorbMessage)>),
+}
+
+pub struct ChannelConnection {
+    reader: Mutex<Receiver<(UorbHeader, UorbMessage)>>,
+    writer: Mutex<ChannelSender>,
+}
+
+impl ChannelConnection {
+    fn new(tx: ChannelSender, rx: Receiver<(UorbHeader, UorbMessage)>) -> ChannelConnection {
+        ChannelConnection {
+            reader: Mutex::new(rx),
+            writer: Mutex::new(tx),
+        }
+    }
+}
+
+impl UorbConnection for ChannelConnection {
+    fn recv(&self) -> io::Result<(UorbHeader, UorbMessage)> {
+        let lock = self.reader.lock().map_err(poisoned)?;
+        match lock.recv_timeout(DEFAULT_READ_TIMEOUT) {
+            Ok(msg) => Ok(msg),
+            Err(RecvTimeoutError::Timeout) => Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "No messages from peer",
+            )),
+            Err(RecvTimeoutError::Disconnected) => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "Peer endpoint dropped",
+            )),
+        }
+    }
+
+    fn try_recv(&self) -> io::Result<Option<(UorbHeader, UorbMessage)>> {
+        let lock = self.reader.lock().map_err(poisoned)?;
+        match lock.try_recv() {
+            Ok(msg) => Ok(Some(msg)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "Peer endpoint dropped",
+            )),
+        }
+    }
+
+    fn send(&self, header: &UorbHeader, data: &UorbMessage) -> io::Result<()> {
+        let lock = self.writer.lock().map_err(poisoned)?;
+        let msg = (header.clone(), data.clone());
+        let res = match *lock {
+            ChannelSender::Unbounded(ref tx) => tx.send(msg).map_err(|_e| ()),
+            ChannelSender::Bounded(ref tx) => tx.send(msg).map_err(|_e| ()),
+        };
+        res.map_err(|_e| io::Error::new(
+            io::ErrorKind::BrokenPipe,
+            "Peer endpoint dropped",
+        ))
+    }
+}
+
+
+/// Background reader
+///
+/// Wraps any connection with a dedicated reader thread that queues messages as they arrive,
+/// so `try_recv` never blocks and `recv` wakes as soon as a message is queued.
+/// The reader thread stops once the wrapper is dropped and the underlying `recv` returns.
+
+pub struct PolledConnection<C: UorbConnection + Send + Sync + 'static> {
+    inner: Arc<C>,
+    inbound: Mutex<Receiver<io::Result<(UorbHeader, UorbMessage)>>>,
+    running: Arc<AtomicBool>,
+}
+
+impl<C: UorbConnection + Send + Sync + 'static> PolledConnection<C> {
+    pub fn new(inner: C) -> Self {
+        let inner = Arc::new(inner);
+        let running = Arc::new(AtomicBool::new(true));
+        let (inbound_tx, inbound_rx) = mpsc::channel();
+
+        thread::spawn({
+            let inner = inner.clone();
+            let running = running.clone();
+            move || {
+                while running.load(Ordering::SeqCst) {
+                    match inner.recv() {
+                        Err(ref e) if is_timeout(e) => continue,
+                        res => {
+                            let failed = res.is_err();
+                            if inbound_tx.send(res).is_err() || failed {
+                                break;
+                            }
+                        },
+                    }
+                }
+            }
+        });
+
+        PolledConnection {
+            inner,
+            inbound: Mutex::new(inbound_rx),
+            running,
+        }
+    }
+
+    /// Wait up to `timeout` for a message
+    pub fn recv_timeout(&self, timeout: Duration) -> io::Result<(UorbHeader, UorbMessage)> {
+        let lock = self.inbound.lock().map_err(poisoned)?;
+        match lock.recv_timeout(timeout) {
+            Ok(res) => res,
+            Err(RecvTimeoutError::Timeout) => Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "No messages available",
+            )),
+            Err(RecvTimeoutError::Disconnected) => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "Reader stopped",
+            )),
+        }
+    }
+}
+
+impl<C: UorbConnection + Send + Sync + 'static> Drop for PolledConnection<C> {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+}
+
+impl<C: UorbConnection + Send + Sync + 'static> UorbConnection for PolledConnection<C> {
+    fn recv(&self) -> io::Result<(UorbHeader, UorbMessage)> {
+        self.recv_timeout(DEFAULT_READ_TIMEOUT)
+    }
+
+    fn try_recv(&self) -> io::Result<Option<(UorbHeader, UorbMessage)>> {
+        let lock = self.inbound.lock().map_err(poisoned)?;
+        match lock.try_recv() {
+            Ok(res) => res.map(Some),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "Reader stopped",
+            )),
+        }
+    }
+
+    fn send(&self, header: &UorbHeader, data: &UorbMessage) -> io::Result<()> {
+        self.inner.send(header, data)
+    }
+
+    fn send_batch(&self, msgs: &[(UorbHeader, UorbMessage)]) -> io::Result<()> {
+        self.inner.send_batch(msgs)
+    }
+
+    fn skipped_bytes(&self) -> u64 {
+        self.inner.skipped_bytes()
+    }
+}
+
+
+/// UDP connection
+///
+/// Each datagram carries exactly one uORB message.
+
+pub fn udpout<T: ToSocketAddrs>(address: T) -> Result<UdpConnection, ConnectionError> {
+    udpout_with(address, &ConnectionOptions::default_for(Protocol::UdpOut))
+}
+
+fn udpout_with<T: ToSocketAddrs>(address: T, options: &ConnectionOptions) -> Result<UdpConnection, ConnectionError> {
+    let addr = resolve_addr(address)?;
+    // bind an ephemeral port of the same address family as the destination
+    let any: SocketAddr = if addr.is_ipv6() {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(&any)?;
+    Ok(UdpConnection::new(socket, false, Some(addr), options)?)
+}
+
+pub fn udpin<T: ToSocketAddrs>(address: T) -> Result<UdpConnection, ConnectionError> {
+    udpin_with(address, &ConnectionOptions::default_for(Protocol::UdpIn))
+}
+
+fn udpin_with<T: ToSocketAddrs>(address: T, options: &ConnectionOptions) -> Result<UdpConnection, ConnectionError> {
+    let addr = resolve_addr(address)?;
+    let socket = UdpSocket::bind(&addr)?;
+    Ok(UdpConnection::new(socket, true, None, options)?)
+}
+
+/// Apply options to a bound UDP socket
+fn configure_udp(socket: UdpSocket, options: &ConnectionOptions) -> io::Result<UdpSocket> {
+    socket.set_read_timeout(options.read_timeout)?;
+    socket.set_write_timeout(options.write_timeout)?;
+    if options.send_buffer_size.is_none() && options.recv_buffer_size.is_none() {
+        return Ok(socket);
+    }
+    let socket = socket2::Socket::from(socket);
+    if let Some(size) = options.send_buffer_size {
+        socket.set_send_buffer_size(size)?;
+    }
+    if let Some(size) = options.recv_buffer_size {
+        socket.set_recv_buffer_size(size)?;
+    }
+    Ok(socket.into_udp_socket())
+}
+
+/// Largest datagram we expect to receive
+const UDP_MAX_DATAGRAM_LEN: usize = 65536;
+
+/// Holds a single received datagram, readable as a byte stream
+struct PacketBuf {
+    buf: Vec<u8>,
+    start: usize,
+    end: usize,
+}
+
+impl PacketBuf {
+    fn new() -> PacketBuf {
+        PacketBuf {
+            buf: vec![0; UDP_MAX_DATAGRAM_LEN],
+            start: 0,
+            end: 0,
+        }
+    }
+
+    fn reset(&mut self) -> &mut [u8] {
+        self.start = 0;
+        self.end = 0;
+        &mut self.buf
+    }
+
+    fn set_len(&mut self, size: usize) {
+        self.end = size;
+    }
+
+    fn slice(&self) -> &[u8] {
+        &self.buf[self.start..self.end]
+    }
+}
+
+impl Read for PacketBuf {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = Read::read(&mut self.slice(), buf)?;
+        self.start += n;
+        Ok(n)
+    }
+}
+
+struct UdpRead {
+    socket: UdpSocket,
+    recv_buf: PacketBuf,
+}
+
+struct UdpWrite {
+    socket: UdpSocket,
+    dest: Option<SocketAddr>,
+}
+
+pub struct UdpConnection {
+    reader: Mutex<UdpRead>,
+    writer: Mutex<UdpWrite>,
+    /// When acting as server, replies go to whichever peer sent to us last
+    server: bool,
+    skipped: AtomicU64,
+}
+
+impl UdpConnection {
+    fn new(socket: UdpSocket,
+           server: bool,
+           dest: Option<SocketAddr>,
+           options: &ConnectionOptions
+    ) -> io::Result<UdpConnection> {
+        let socket = configure_udp(socket, options)?;
+        Ok(UdpConnection {
+            server,
+            reader: Mutex::new(UdpRead {
+                socket: socket.try_clone()?,
+                recv_buf: PacketBuf::new(),
+            }),
+            writer: Mutex::new(UdpWrite {
+                socket,
+                dest,
+            }),
+            skipped: AtomicU64::new(0),
+        })
+    }
+
+    /// The address the socket is bound to, eg to find the port chosen when binding port 0
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.writer.lock().map_err(poisoned)?.socket.local_addr()
+    }
+}
+
+impl UorbConnection for UdpConnection {
+    fn recv(&self) -> io::Result<(UorbHeader, UorbMessage)> {
+        let mut guard = self.reader.lock().map_err(poisoned)?;
+        let state = &mut *guard;
+        loop {
+            let (len, src) = state.socket.recv_from(state.recv_buf.reset())?;
+            state.recv_buf.set_len(len);
+            if self.server {
+                self.writer.lock().map_err(poisoned)?.dest = Some(src);
+            }
+            // A truncated or corrupt datagram is dropped whole:
+            // the next datagram starts a fresh frame.
+            if let Ok((header, msg)) = read_msg(&mut state.recv_buf) {
+                return Ok((header, msg));
+            }
+            self.skipped.fetch_add(len as u64, Ordering::SeqCst);
+        }
+    }
+
+    fn skipped_bytes(&self) -> u64 {
+        self.skipped.load(Ordering::SeqCst)
+    }
+
+    /// A server has nowhere to send until a peer has sent to it: until then this fails with `NotConnected`
+    fn send(&self, header: &UorbHeader, data: &UorbMessage) -> io::Result<()> {
+        let guard = self.writer.lock().map_err(poisoned)?;
+        let addr = guard.dest.ok_or_else(|| io::Error::new(
+            io::ErrorKind::NotConnected,
+            "No datagram received from a peer yet",
+        ))?;
+        let mut buf = Vec::new();
+        write_msg(&mut buf, header, data)?;
+        guard.socket.send_to(&buf, addr)?;
+        Ok(())
+    }
+}
+
+
+/// Unix domain socket connection
+///
+/// Useful when the simulator and px4_sitl share a host, avoiding TCP loopback overhead.
+
+#[cfg(unix)]
+fn unix_path(address: &str) -> Result<&Path, ConnectionError> {
+    if address.is_empty() {
+        return Err(ConnectionError::InvalidPath(address.to_string()));
+    }
+    Ok(Path::new(address))
+}
+
+#[cfg(unix)]
+pub fn unix<P: AsRef<Path>>(path: P) -> Result<UnixConnection, ConnectionError> {
+    unix_with(path, &ConnectionOptions::default_for(Protocol::Unix))
+}
+
+#[cfg(unix)]
+fn unix_with<P: AsRef<Path>>(path: P, options: &ConnectionOptions) -> Result<UnixConnection, ConnectionError> {
+    let socket = UnixStream::connect(path)?;
+    Ok(UnixConnection::new(socket, options)?)
+}
+
+#[cfg(unix)]
+pub fn unixin<P: AsRef<Path>>(path: P) -> Result<UnixConnection, ConnectionError> {
+    unixin_with(path, &ConnectionOptions::default_for(Protocol::UnixIn))
+}
+
+#[cfg(unix)]
+fn unixin_with<P: AsRef<Path>>(path: P, options: &ConnectionOptions) -> Result<UnixConnection, ConnectionError> {
+    let path = path.as_ref();
+    // a stale socket file left by a previous run would prevent binding,
+    // but anything other than a socket is not ours to remove
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+        Ok(_) => return Err(ConnectionError::InvalidPath(path.display().to_string())),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+        Err(e) => return Err(e.into()),
+    }
+    let listener = UnixListener::bind(path)?;
+
+    //For now we only accept one incoming stream: this blocks until we get one
+    let (socket, _addr) = listener.accept()?;
+    Ok(UnixConnection::new(socket, options)?)
+}
+
+#[cfg(unix)]
+pub struct UnixConnection {
+    reader: Mutex<ResyncReader<UnixStream>>,
+    writer: Mutex<UnixStream>,
+    skipped: Arc<AtomicU64>,
+}
+
+#[cfg(unix)]
+impl UnixConnection {
+    fn new(socket: UnixStream, options: &ConnectionOptions) -> io::Result<UnixConnection> {
+        socket.set_read_timeout(options.read_timeout)?;
+        socket.set_write_timeout(options.write_timeout)?;
+        let skipped = Arc::new(AtomicU64::new(0));
+        Ok(UnixConnection {
+            reader: Mutex::new(ResyncReader::new(socket.try_clone()?, skipped.clone())),
+            writer: Mutex::new(socket),
+            skipped,
+        })
+    }
+}
+
+#[cfg(unix)]
+impl UorbConnection for UnixConnection {
+    fn recv(&self) -> io::Result<(UorbHeader, UorbMessage)> {
+        let mut lock = self.reader.lock().map_err(poisoned)?;
+        lock.recv()
+    }
+
+    fn skipped_bytes(&self) -> u64 {
+        self.skipped.load(Ordering::SeqCst)
+    }
+
+    fn send(&self, header: &UorbHeader, data: &UorbMessage) -> io::Result<()> {
+        let mut lock = self.writer.lock().map_err(poisoned)?;
+        write_msg(&mut *lock, header, data)
+    }
+
+    fn send_batch(&self, msgs: &[(UorbHeader, UorbMessage)]) -> io::Result<()> {
+        let mut buf = Vec::new();
+        encode_batch(&mut buf, msgs)?;
+        let mut lock = self.writer.lock().map_err(poisoned)?;
+        lock.write_all(&buf)
+    }
+}
+
+
+/// Largest frame we expect to assemble from a byte stream, header included
+const MAX_FRAME_LEN: usize = 1024;
+
+/// Offset of the first possible frame start, a `UORB_MAGIC_V1`, in `buf`.
+/// A trailing partial magic counts, as the rest of it may not have arrived yet.
+fn find_frame_start(buf: &[u8]) -> usize {
+    let magic = UORB_MAGIC_V1.to_le_bytes();
+    (0..buf.len())
+        .find(|&idx| {
+            let tail = &buf[idx..];
+            let n = tail.len().min(magic.len());
+            tail[..n] == magic[..n]
+        })
+        .unwrap_or(buf.len())
+}
+
+/// Encoded length of a frame: the header fields are packed, followed by the payload
+fn frame_len(header: &UorbHeader) -> usize {
+    mem::size_of_val(&header.version)
+        + mem::size_of_val(&header.hash)
+        + mem::size_of_val(&header.timestamp)
+        + mem::size_of_val(&header.instance_id)
+        + mem::size_of_val(&header.payload_len)
+        + header.payload_len as usize
+}
+
+/// Assembles uORB frames from a byte stream that may contain garbage,
+/// eg line noise on a UART, a corrupt or truncated frame, or a reader that attached mid-frame.
+///
+/// Bytes before a `UORB_MAGIC_V1` cannot start a frame, and are discarded and counted in `skipped`.
+/// A frame that fails to decode is skipped up to the next magic, until `read_msg` finds a header
+/// with a known hash that decodes cleanly.
+/// A partial frame is kept across read timeouts, so a timeout never desynchronizes the stream.
+struct ResyncReader<R: Read> {
+    inner: R,
+    recv_buf: Vec<u8>,
+    skipped: Arc<AtomicU64>,
+}
+
+impl<R: Read> ResyncReader<R> {
+    fn new(inner: R, skipped: Arc<AtomicU64>) -> ResyncReader<R> {
+        ResyncReader {
+            inner,
+            recv_buf: Vec::with_capacity(2 * MAX_FRAME_LEN),
+            skipped,
+        }
+    }
+
+    /// Discard the first `count` buffered bytes, as garbage
+    fn skip(&mut self, count: usize) {
+        if count > 0 {
+            self.recv_buf.drain(..count);
+            self.skipped.fetch_add(count as u64, Ordering::SeqCst);
+        }
+    }
+
+    /// Try to decode one frame from the bytes already buffered
+    fn try_decode(&mut self) -> Option<(UorbHeader, UorbMessage)> {
+        loop {
+            let start = find_frame_start(&self.recv_buf);
+            self.skip(start);
+            if self.recv_buf.len() < mem::size_of_val(&UORB_MAGIC_V1) {
+                return None;
+            }
+
+            let mut cursor = Cursor::new(&self.recv_buf[..]);
+            match read_msg(&mut cursor) {
+                Ok((header, msg)) => {
+                    let used = cursor.position() as usize;
+                    // read_msg may itself scan past garbage to the next magic:
+                    // whatever it consumed beyond the frame was skipped
+                    let frame_len = frame_len(&header).min(used);
+                    if used > frame_len {
+                        self.skipped.fetch_add((used - frame_len) as u64, Ordering::SeqCst);
+                    }
+                    self.recv_buf.drain(..used);
+                    return Some((header, msg));
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof
+                    && self.recv_buf.len() < 2 * MAX_FRAME_LEN => {
+                    // possibly a partial frame: wait for more bytes
+                    return None;
+                },
+                Err(_) => {
+                    // not the start of a valid frame: jump to the next magic
+                    let next = 1 + find_frame_start(&self.recv_buf[1..]);
+                    self.skip(next);
+                },
+            }
+        }
+    }
+
+    fn recv(&mut self) -> io::Result<(UorbHeader, UorbMessage)> {
+        let mut chunk = [0u8; MAX_FRAME_LEN];
+        loop {
+            if let Some(msg) = self.try_decode() {
+                return Ok(msg);
+            }
+            let n = self.inner.read(&mut chunk)?;
+            if 0 == n {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "stream closed"));
+            }
+            self.recv_buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
+
+
+/// Serial port connection
+///
+/// Selector format is `serial:<port>:<baud>`, eg `serial:/dev/ttyUSB0:921600`
+
+pub fn open_serial(settings: &str) -> Result<SerialConnection, ConnectionError> {
+    open_serial_with(settings, &ConnectionOptions::default_for(Protocol::Serial))
+}
+
+/// Serial ports have no "block forever" setting, so an unbounded read timeout maps to this
+const SERIAL_MAX_READ_TIMEOUT: Duration = Duration::from_secs(3600);
+
+fn open_serial_with(settings: &str, options: &ConnectionOptions) -> Result<SerialConnection, ConnectionError> {
+    let (port_name, baud_rate) = parse_serial_settings(settings)?;
+
+    let mut port_settings = SerialPortSettings::default();
+    port_settings.baud_rate = baud_rate;
+    port_settings.timeout = options.read_timeout.unwrap_or(SERIAL_MAX_READ_TIMEOUT);
+    let port = serialport::open_with_settings(port_name, &port_settings)
+        .map_err(io::Error::from)?;
+    Ok(SerialConnection::new(port)?)
+}
+
+pub struct SerialConnection {
+    reader: Mutex<ResyncReader<Box<dyn SerialPort>>>,
+    writer: Mutex<Box<dyn SerialPort>>,
+    skipped: Arc<AtomicU64>,
+}
+
+impl SerialConnection {
+    /// Wrap an already-opened port, eg one end of a pseudo-terminal pair
+    pub fn new(port: Box<dyn SerialPort>) -> io::Result<SerialConnection> {
+        let skipped = Arc::new(AtomicU64::new(0));
+        Ok(SerialConnection {
+            reader: Mutex::new(ResyncReader::new(port.try_clone()?, skipped.clone())),
+            writer: Mutex::new(port),
+            skipped,
+        })
+    }
+}
+
+impl UorbConnection for SerialConnection {
+    fn recv(&self) -> io::Result<(UorbHeader, UorbMessage)> {
+        let mut lock = self.reader.lock().map_err(poisoned)?;
+        lock.recv()
+    }
+
+    fn skipped_bytes(&self) -> u64 {
+        self.skipped.load(Ordering::SeqCst)
+    }
+
+    fn send(&self, header: &UorbHeader, data: &UorbMessage) -> io::Result<()> {
+        let mut lock = self.writer.lock().map_err(poisoned)?;
+        write_msg(&mut *lock, header, data)
+    }
+
+    fn send_batch(&self, msgs: &[(UorbHeader, UorbMessage)]) -> io::Result<()> {
+        let mut buf = Vec::new();
+        encode_batch(&mut buf, msgs)?;
+        let mut lock = self.writer.lock().map_err(poisoned)?;
+        lock.write_all(&buf)
+    }
 }
@@ -0,0 +1,46 @@
+/// Standard sea level pressure, in pascals
+const SEA_LEVEL_PRESSURE: f32 = 101_325.0;
+/// Standard sea level temperature, in kelvin
+const SEA_LEVEL_TEMPERATURE: f32 = 288.15;
+/// Temperature lapse rate of the troposphere, in kelvin per meter
+const LAPSE_RATE: f32 = 0.0065;
+/// g * M / (R * L) for dry air
+const PRESSURE_EXPONENT: f32 = 5.255_88;
+
+/// Static pressure in the International Standard Atmosphere at `alt_amsl` meters above mean sea level, in pascals
+pub fn standard_pressure(alt_amsl: f32) -> f32 {
+    SEA_LEVEL_PRESSURE * (1.0 - LAPSE_RATE * alt_amsl / SEA_LEVEL_TEMPERATURE).powf(PRESSURE_EXPONENT)
+}
+
+/// The height of mean sea level (the geoid) relative to the WGS84 ellipsoid around the home location.
+///
+/// The undulation varies slowly, by at most a few meters over tens of kilometers,
+/// so a constant taken from EGM96 at the home location is good for any simulated flight.
+/// An undulation of zero treats the ellipsoid as sea level.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Geoid {
+    /// Geoid height above the ellipsoid, in meters: eg about -32m in the San Francisco bay area
+    pub undulation: f32,
+}
+
+impl Geoid {
+    pub fn new(undulation: f32) -> Self {
+        Geoid { undulation }
+    }
+
+    /// Altitude above mean sea level, from altitude above the ellipsoid
+    pub fn amsl_from_ellipsoid(&self, alt_ellipsoid: f32) -> f32 {
+        alt_ellipsoid - self.undulation
+    }
+
+    /// Altitude above the ellipsoid, from altitude above mean sea level
+    pub fn ellipsoid_from_amsl(&self, alt_amsl: f32) -> f32 {
+        alt_amsl + self.undulation
+    }
+
+    /// Scale for a pressure computed as if `alt_ellipsoid` were the height above sea level,
+    /// giving the pressure at the true height above sea level
+    pub fn pressure_scale(&self, alt_ellipsoid: f32) -> f32 {
+        standard_pressure(self.amsl_from_ellipsoid(alt_ellipsoid)) / standard_pressure(alt_ellipsoid)
+    }
+}
@@ -0,0 +1,329 @@
+use std::f32::consts::PI;
+use std::time::Duration;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use flighty::physical_types::{LatLonUnits, TimeBaseUnits};
+
+use crate::random::standard_normal;
+use crate::sim_clock::SimClock;
+
+/// Fix types, as per PX4 `vehicle_gps_position.fix_type`
+pub const GPS_FIX_TYPE_NONE: u8 = 0;
+pub const GPS_FIX_TYPE_3D: u8 = 3;
+
+/// Satellites needed for a 3D fix: one each for position, and one more for the receiver clock
+const MIN_SATELLITES_3D: u8 = 4;
+
+/// Mean radius of the earth, in meters
+const EARTH_RADIUS: LatLonUnits = 6_371_000.0;
+
+/// HDOP with `REFERENCE_SATELLITES` in view: geometry worsens as satellites are lost
+const REFERENCE_HDOP: f32 = 0.8;
+const REFERENCE_SATELLITES: u8 = 11;
+/// Vertical geometry is always weaker than horizontal
+const VDOP_PER_HDOP: f32 = 1.6;
+
+/// How much larger the error is just after a fix is reacquired, relative to a settled fix
+const REACQUISITION_ERROR_SCALE: f32 = 3.0;
+
+/// A scheduled loss of fix, measured from the first GPS sample
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FixOutage {
+    pub start: Duration,
+    pub duration: Duration,
+}
+
+impl FixOutage {
+    fn contains(&self, elapsed: Duration) -> bool {
+        elapsed >= self.start && elapsed < self.start + self.duration
+    }
+}
+
+/// Configuration for a `GpsErrorModel`
+#[derive(Debug, Clone, PartialEq)]
+pub struct GpsErrorConfig {
+    /// User equivalent range error, in meters (1 sigma): scaled by DOP to give the position error
+    pub range_error: f32,
+    /// Correlation time of the position drift, in seconds
+    pub drift_time_constant: f32,
+    /// White noise on each velocity axis, in m/s (1 sigma)
+    pub velocity_noise: f32,
+    /// Satellites in view at the start
+    pub satellites: u8,
+    /// Bounds of the satellite count as it varies. There are always enough for a 3D fix,
+    /// except during an outage.
+    pub min_satellites: u8,
+    pub max_satellites: u8,
+    /// Mean time between changes of the satellite count, in seconds
+    pub satellite_change_interval: f32,
+    pub outages: Vec<FixOutage>,
+    /// Seed for the random number generator: the same seed gives the same errors
+    pub seed: u64,
+}
+
+impl Default for GpsErrorConfig {
+    /// An ideal receiver with a steady view of the sky
+    fn default() -> Self {
+        GpsErrorConfig {
+            range_error: 0.01,
+            drift_time_constant: 60.0,
+            velocity_noise: 0.0,
+            satellites: REFERENCE_SATELLITES,
+            min_satellites: REFERENCE_SATELLITES,
+            max_satellites: REFERENCE_SATELLITES,
+            satellite_change_interval: 20.0,
+            outages: vec![],
+            seed: 0,
+        }
+    }
+}
+
+impl GpsErrorConfig {
+    /// A typical consumer GNSS receiver, in open sky
+    pub fn typical() -> Self {
+        GpsErrorConfig {
+            range_error: 2.0,
+            velocity_noise: 0.1,
+            min_satellites: 6,
+            max_satellites: 16,
+            ..GpsErrorConfig::default()
+        }
+    }
+}
+
+/// Horizontal dilution of precision with `satellites` in view
+pub fn hdop_for_satellites(satellites: u8) -> f32 {
+    if satellites < MIN_SATELLITES_3D {
+        return std::f32::INFINITY;
+    }
+    // each satellite beyond the minimum adds redundancy
+    let redundant = (satellites - MIN_SATELLITES_3D + 1) as f32;
+    REFERENCE_HDOP * ((REFERENCE_SATELLITES - MIN_SATELLITES_3D + 1) as f32 / redundant).sqrt()
+}
+
+/// Move a position in degrees by `north` and `east` meters
+pub fn offset_lat_lon(lat: LatLonUnits, lon: LatLonUnits, north: f32, east: f32) -> (LatLonUnits, LatLonUnits) {
+    let dlat = (north as LatLonUnits / EARTH_RADIUS).to_degrees();
+    let dlon = (east as LatLonUnits / (EARTH_RADIUS * lat.to_radians().cos())).to_degrees();
+    (lat + dlat, lon + dlon)
+}
+
+/// The errors to apply to one GPS report, and the accuracy to claim for it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GpsFix {
+    /// One of the `GPS_FIX_TYPE_*` values
+    pub fix_type: u8,
+    pub satellites_used: u8,
+    pub hdop: f32,
+    pub vdop: f32,
+    /// Position error, north, east and down, in meters
+    pub position_error: [f32; 3],
+    /// Velocity error, north, east and down, in m/s
+    pub velocity_error: [f32; 3],
+    /// Horizontal and vertical position accuracy, in meters (1 sigma), matching `position_error`
+    pub eph: f32,
+    pub epv: f32,
+    /// Speed accuracy, in m/s (1 sigma), matching `velocity_error`
+    pub speed_accuracy: f32,
+}
+
+impl GpsFix {
+    pub fn has_fix(&self) -> bool {
+        GPS_FIX_TYPE_NONE != self.fix_type
+    }
+
+    /// Course accuracy in radians (1 sigma), moving at `ground_speed`
+    pub fn course_accuracy(&self, ground_speed: f32) -> f32 {
+        if ground_speed <= 0.0 {
+            return PI;
+        }
+        (self.speed_accuracy / ground_speed).min(PI)
+    }
+
+    /// Variance of the velocity error on each axis, in (m/s)^2, as per PX4 `s_variance_m_s`
+    pub fn speed_variance(&self) -> f32 {
+        self.speed_accuracy * self.speed_accuracy
+    }
+
+    /// Variance of the course error in rad^2, moving at `ground_speed`, as per PX4 `c_variance_rad`
+    pub fn course_variance(&self, ground_speed: f32) -> f32 {
+        let accuracy = self.course_accuracy(ground_speed);
+        accuracy * accuracy
+    }
+}
+
+/// Generates realistic GNSS errors over simulated time.
+///
+/// Position error is a first-order Gauss-Markov drift with a standard deviation of
+/// `range_error` times the dilution of precision, which rises as satellites drop out of view.
+/// The reported `eph` and `epv` are that standard deviation, so they agree with the error injected.
+/// During a scheduled outage there is no fix; afterwards the fix resumes with a larger error
+/// that settles over the drift time constant.
+#[derive(Debug, Clone)]
+pub struct GpsErrorModel {
+    config: GpsErrorConfig,
+    rng: ChaCha8Rng,
+    start_time: Option<TimeBaseUnits>,
+    clock: SimClock,
+    drift: [f32; 3],
+    satellites: u8,
+    error_scale: f32,
+    in_outage: bool,
+}
+
+impl Default for GpsErrorModel {
+    fn default() -> Self {
+        GpsErrorModel::new(GpsErrorConfig::default())
+    }
+}
+
+impl GpsErrorModel {
+    pub fn new(mut config: GpsErrorConfig) -> Self {
+        let rng = ChaCha8Rng::seed_from_u64(config.seed);
+        config.min_satellites = config.min_satellites.max(MIN_SATELLITES_3D);
+        config.max_satellites = config.max_satellites.max(config.min_satellites);
+        let satellites = config.satellites.max(config.min_satellites).min(config.max_satellites);
+        let mut model = GpsErrorModel {
+            config,
+            rng,
+            start_time: None,
+            clock: SimClock::default(),
+            drift: [0.0; 3],
+            satellites,
+            error_scale: 1.0,
+            in_outage: false,
+        };
+        // start from a settled fix
+        model.drift = model.draw_drift();
+        model
+    }
+
+    pub fn config(&self) -> &GpsErrorConfig {
+        &self.config
+    }
+
+    /// Advance to `time` (microseconds) and generate the errors for a report at that time.
+    ///
+    /// Simulated time running backwards means the simulator restarted: the schedule starts over.
+    pub fn sample(&mut self, time: TimeBaseUnits) -> GpsFix {
+        let (dt, restarted) = self.clock.advance(time);
+        if restarted {
+            self.start_time = None;
+        }
+        let start_time = *self.start_time.get_or_insert(time);
+
+        let elapsed = Duration::from_micros(time - start_time);
+        let in_outage = self.config.outages.iter().any(|outage| outage.contains(elapsed));
+        if in_outage {
+            self.in_outage = true;
+            return self.no_fix();
+        }
+
+        self.update_satellites(dt);
+        let decay = self.decay(dt);
+        if self.in_outage {
+            // reacquired: a fresh solution, not yet settled
+            self.in_outage = false;
+            self.error_scale = REACQUISITION_ERROR_SCALE;
+            self.drift = self.draw_drift();
+        }
+        else {
+            self.error_scale = 1.0 + (self.error_scale - 1.0) * decay;
+            // exact discretization of the Gauss-Markov process, so the spread stays at sigma for any dt
+            let fresh = self.draw_drift();
+            let innovation = (1.0 - decay * decay).sqrt();
+            for (drift, fresh) in self.drift.iter_mut().zip(fresh.iter()) {
+                *drift = *drift * decay + fresh * innovation;
+            }
+        }
+
+        let velocity_noise = self.config.velocity_noise;
+        let velocity_error = [
+            self.gaussian() * velocity_noise,
+            self.gaussian() * velocity_noise,
+            self.gaussian() * velocity_noise,
+        ];
+        let (hdop, vdop) = self.dop();
+        let (eph, epv) = self.accuracy();
+        GpsFix {
+            fix_type: GPS_FIX_TYPE_3D,
+            satellites_used: self.satellites,
+            hdop,
+            vdop,
+            position_error: self.drift,
+            velocity_error,
+            eph,
+            epv,
+            speed_accuracy: velocity_noise,
+        }
+    }
+
+    fn no_fix(&self) -> GpsFix {
+        GpsFix {
+            fix_type: GPS_FIX_TYPE_NONE,
+            satellites_used: 0,
+            hdop: std::f32::INFINITY,
+            vdop: std::f32::INFINITY,
+            position_error: [0.0; 3],
+            velocity_error: [0.0; 3],
+            eph: std::f32::INFINITY,
+            epv: std::f32::INFINITY,
+            speed_accuracy: std::f32::INFINITY,
+        }
+    }
+
+    /// Fraction of the drift that survives `dt` seconds
+    fn decay(&self, dt: f32) -> f32 {
+        if self.config.drift_time_constant <= 0.0 {
+            return 0.0;
+        }
+        (-dt / self.config.drift_time_constant).exp()
+    }
+
+    /// Occasionally gain or lose a satellite
+    fn update_satellites(&mut self, dt: f32) {
+        if self.config.satellite_change_interval <= 0.0 {
+            return;
+        }
+        if self.rng.gen::<f32>() < dt / self.config.satellite_change_interval {
+            self.satellites = if self.rng.gen::<bool>() {
+                self.satellites.saturating_add(1)
+            }
+            else {
+                self.satellites.saturating_sub(1)
+            };
+            self.satellites = self.satellites.max(self.config.min_satellites).min(self.config.max_satellites);
+        }
+    }
+
+    fn dop(&self) -> (f32, f32) {
+        let hdop = hdop_for_satellites(self.satellites);
+        (hdop, hdop * VDOP_PER_HDOP)
+    }
+
+    /// Horizontal (radial) and vertical standard deviation of the position error
+    fn accuracy(&self) -> (f32, f32) {
+        let (hdop, vdop) = self.dop();
+        let scale = self.config.range_error * self.error_scale;
+        (scale * hdop, scale * vdop)
+    }
+
+    /// Position error drawn from the steady state distribution
+    fn draw_drift(&mut self) -> [f32; 3] {
+        let (eph, epv) = self.accuracy();
+        // eph is radial, so each horizontal axis has eph / sqrt(2)
+        let axis_sigma = eph / std::f32::consts::SQRT_2;
+        [
+            self.gaussian() * axis_sigma,
+            self.gaussian() * axis_sigma,
+            self.gaussian() * epv,
+        ]
+    }
+
+    /// A standard normal variate from the seeded generator
+    fn gaussian(&mut self) -> f32 {
+        standard_normal(&mut self.rng) as f32
+    }
+}
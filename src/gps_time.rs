@@ -0,0 +1,253 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use flighty::physical_types::TimeBaseUnits;
+
+const MICROS_PER_SECOND: u64 = 1_000_000;
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// Days between 0000-03-01 and 1970-01-01, in the proleptic Gregorian calendar
+const DAYS_TO_UNIX_EPOCH: i64 = 719_468;
+const DAYS_PER_ERA: i64 = 146_097;
+
+/// 9999-12-31T23:59:59Z, the last time a four digit year can represent
+const LATEST_UNIX_SECONDS: u64 = 253_402_300_799;
+
+/// How often the receiver computes a navigation solution: 5Hz, aligned to whole UTC seconds
+const DEFAULT_MEASUREMENT_PERIOD: TimeBaseUnits = 200_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GpsTimeError {
+    /// Not a date and time of the form `YYYY-MM-DDTHH:MM:SSZ`
+    InvalidDateTime(String),
+    /// Not `now`, a date and time, or whole seconds since the unix epoch
+    InvalidEpoch(String),
+}
+
+impl fmt::Display for GpsTimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GpsTimeError::InvalidDateTime(s) => write!(f, "invalid UTC date and time '{}', expected YYYY-MM-DDTHH:MM:SSZ", s),
+            GpsTimeError::InvalidEpoch(s) => write!(f, "invalid epoch '{}', expected now, YYYY-MM-DDTHH:MM:SSZ or unix seconds", s),
+        }
+    }
+}
+
+impl Error for GpsTimeError {}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // count years from March, so that the leap day falls at the end of the year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * DAYS_PER_ERA + day_of_era - DAYS_TO_UNIX_EPOCH
+}
+
+/// The date of a day counted from 1970-01-01: the inverse of `days_from_civil`
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + DAYS_TO_UNIX_EPOCH;
+    let era = days.div_euclid(DAYS_PER_ERA);
+    let day_of_era = days - era * DAYS_PER_ERA;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn is_leap_year(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// A UTC calendar date and time, without leap seconds: every day is 86400 seconds long, as in unix time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UtcDateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub micros: u32,
+}
+
+impl UtcDateTime {
+    /// The date and time, if it is a valid one no earlier than 1970
+    pub fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Option<UtcDateTime> {
+        let valid = year >= 1970
+            && (1..=12).contains(&month)
+            && day >= 1 && day as i64 <= days_in_month(year as i64, month as i64)
+            && hour < 24 && minute < 60 && second < 60;
+        if !valid {
+            return None;
+        }
+        Some(UtcDateTime { year, month, day, hour, minute, second, micros: 0 })
+    }
+
+    /// The date and time `unix_micros` after the unix epoch, which must be no later than the year 9999
+    pub fn from_unix_micros(unix_micros: u64) -> UtcDateTime {
+        let secs = unix_micros / MICROS_PER_SECOND;
+        let (year, month, day) = civil_from_days((secs / SECONDS_PER_DAY) as i64);
+        let secs_of_day = secs % SECONDS_PER_DAY;
+        UtcDateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (secs_of_day / 3600) as u8,
+            minute: (secs_of_day / 60 % 60) as u8,
+            second: (secs_of_day % 60) as u8,
+            micros: (unix_micros % MICROS_PER_SECOND) as u32,
+        }
+    }
+
+    /// Microseconds since 1970-01-01T00:00:00Z, not counting leap seconds
+    pub fn unix_micros(&self) -> u64 {
+        let days = days_from_civil(self.year as i64, self.month as i64, self.day as i64) as u64;
+        let secs = days * SECONDS_PER_DAY + self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64;
+        secs * MICROS_PER_SECOND + self.micros as u64
+    }
+}
+
+impl FromStr for UtcDateTime {
+    type Err = GpsTimeError;
+
+    /// Parse `YYYY-MM-DDTHH:MM:SSZ`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || GpsTimeError::InvalidDateTime(s.to_string());
+        let bytes = s.as_bytes();
+        let separators_ok = 20 == bytes.len()
+            && b'-' == bytes[4] && b'-' == bytes[7] && b'T' == bytes[10]
+            && b':' == bytes[13] && b':' == bytes[16] && b'Z' == bytes[19];
+        if !separators_ok {
+            return Err(invalid());
+        }
+        let field = |start: usize, end: usize| -> Result<u16, GpsTimeError> {
+            let digits = &s[start..end];
+            if !digits.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            digits.parse().map_err(|_e| invalid())
+        };
+        UtcDateTime::new(field(0, 4)?,
+                         field(5, 7)? as u8,
+                         field(8, 10)? as u8,
+                         field(11, 13)? as u8,
+                         field(14, 16)? as u8,
+                         field(17, 19)? as u8)
+            .ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for UtcDateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+               self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+}
+
+/// The UTC time at which simulated time starts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SimulationEpoch {
+    /// The wall clock time when the simulator starts
+    Now,
+    /// A fixed time, for reproducible runs
+    Fixed(UtcDateTime),
+}
+
+impl SimulationEpoch {
+    /// Microseconds since the unix epoch, reading the wall clock for `Now`
+    pub fn unix_micros(&self) -> u64 {
+        match self {
+            SimulationEpoch::Now => SystemTime::now().duration_since(UNIX_EPOCH)
+                .map(|since| since.as_micros() as u64)
+                .unwrap_or(0),
+            SimulationEpoch::Fixed(start) => start.unix_micros(),
+        }
+    }
+}
+
+impl FromStr for SimulationEpoch {
+    type Err = GpsTimeError;
+
+    /// Parse `now`, `YYYY-MM-DDTHH:MM:SSZ`, or whole seconds since the unix epoch
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if "now" == s {
+            return Ok(SimulationEpoch::Now);
+        }
+        if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+            let secs: u64 = s.parse().ok()
+                .filter(|secs| *secs <= LATEST_UNIX_SECONDS)
+                .ok_or_else(|| GpsTimeError::InvalidEpoch(s.to_string()))?;
+            return Ok(SimulationEpoch::Fixed(UtcDateTime::from_unix_micros(secs * MICROS_PER_SECOND)));
+        }
+        s.parse().map(SimulationEpoch::Fixed).map_err(|_e| GpsTimeError::InvalidEpoch(s.to_string()))
+    }
+}
+
+/// The UTC time reported by the simulated GPS.
+///
+/// Simulated time zero is the epoch. Solutions are computed on whole multiples of the
+/// measurement period in UTC, as a receiver does, so each report carries the UTC time of
+/// its solution and, relative to the report's timestamp, when that solution was valid.
+#[derive(Debug, Clone)]
+pub struct GpsClock {
+    epoch_micros: u64,
+    measurement_period: TimeBaseUnits,
+}
+
+impl Default for GpsClock {
+    fn default() -> Self {
+        GpsClock::new(SimulationEpoch::Now)
+    }
+}
+
+impl GpsClock {
+    /// A clock starting at `epoch`, which for `SimulationEpoch::Now` is read once, here
+    pub fn new(epoch: SimulationEpoch) -> Self {
+        GpsClock {
+            epoch_micros: epoch.unix_micros(),
+            measurement_period: DEFAULT_MEASUREMENT_PERIOD,
+        }
+    }
+
+    /// Compute solutions every `period` microseconds instead of at 5Hz
+    pub fn with_measurement_period(mut self, period: TimeBaseUnits) -> Self {
+        self.measurement_period = period.max(1);
+        self
+    }
+
+    /// The UTC time at simulated time zero, in microseconds since the unix epoch
+    pub fn epoch_micros(&self) -> u64 {
+        self.epoch_micros
+    }
+
+    /// UTC at `simulated_time`, in microseconds since the unix epoch
+    pub fn utc_micros(&self, simulated_time: TimeBaseUnits) -> u64 {
+        self.epoch_micros + simulated_time
+    }
+
+    /// For a report at `simulated_time`: the UTC time of its solution, in microseconds since the
+    /// unix epoch, and the offset of that solution from `simulated_time`, as per PX4 `timestamp_time_relative`
+    pub fn solution_time(&self, simulated_time: TimeBaseUnits) -> (u64, i32) {
+        let utc = self.utc_micros(simulated_time);
+        // no solution predates the epoch
+        let age = (utc % self.measurement_period).min(simulated_time);
+        (utc - age, -(age as i32))
+    }
+}
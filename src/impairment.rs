@@ -0,0 +1,443 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use uorb_codec::{UorbHeader, UorbMessage};
+
+use crate::connection::{is_timeout, poisoned, UorbConnection};
+use crate::random::standard_normal;
+
+/// How long `ImpairedConnection::recv` waits for a message before reporting `WouldBlock`
+const RECV_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How long the inbound pump waits before polling the underlying connection again
+const PUMP_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Distribution of the one-way delay applied to each message
+#[derive(Debug, Clone, PartialEq)]
+pub enum DelayDistribution {
+    /// Deliver immediately
+    Zero,
+    /// The same delay for every message
+    Constant(Duration),
+    /// Uniformly distributed between `min` and `max`
+    Uniform { min: Duration, max: Duration },
+    /// Normally distributed around `mean`, clamped at zero
+    Normal { mean: Duration, std_dev: Duration },
+}
+
+impl DelayDistribution {
+    fn sample<R: Rng>(&self, rng: &mut R) -> Duration {
+        match *self {
+            DelayDistribution::Zero => Duration::from_secs(0),
+            DelayDistribution::Constant(delay) => delay,
+            DelayDistribution::Uniform { min, max } => {
+                if max <= min {
+                    return min;
+                }
+                min + (max - min).mul_f64(rng.gen::<f64>())
+            },
+            DelayDistribution::Normal { mean, std_dev } => {
+                let secs = mean.as_secs_f64() + standard_normal(rng) * std_dev.as_secs_f64();
+                Duration::from_secs_f64(secs.max(0.0))
+            },
+        }
+    }
+}
+
+/// Impairments applied to the messages travelling in one direction
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImpairmentProfile {
+    /// Latency and jitter. Messages stay in order unless selected for reordering.
+    pub delay: DelayDistribution,
+    /// Probability (0..1) that a message is silently lost
+    pub drop_probability: f64,
+    /// Probability (0..1) that a message is delivered twice
+    pub duplicate_probability: f64,
+    /// Probability (0..1) that a message is held back, letting later messages overtake it
+    pub reorder_probability: f64,
+    /// Extra delay applied to a message selected for reordering
+    pub reorder_hold: Duration,
+}
+
+impl Default for ImpairmentProfile {
+    /// A perfect link
+    fn default() -> Self {
+        ImpairmentProfile {
+            delay: DelayDistribution::Zero,
+            drop_probability: 0.0,
+            duplicate_probability: 0.0,
+            reorder_probability: 0.0,
+            reorder_hold: Duration::from_millis(20),
+        }
+    }
+}
+
+/// Configuration for an `ImpairedConnection`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ImpairmentConfig {
+    /// Applied to messages passed to `send`
+    pub send: ImpairmentProfile,
+    /// Applied to messages returned from `recv`
+    pub recv: ImpairmentProfile,
+    /// Seed for the random number generator: the same seed gives the same impairments
+    pub seed: u64,
+}
+
+/// Decides the fate of each message travelling in one direction
+struct Impairer {
+    profile: ImpairmentProfile,
+    rng: ChaCha8Rng,
+}
+
+impl Impairer {
+    fn new(profile: ImpairmentProfile, seed: u64) -> Self {
+        Impairer {
+            profile,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+
+    /// The delivery delays for one message: empty if dropped, two if duplicated.
+    /// The flag marks a delivery that may overtake, or be overtaken by, its neighbours.
+    fn schedule(&mut self) -> Vec<(Duration, bool)> {
+        let mut deliveries = vec![];
+        if self.rng.gen::<f64>() < self.profile.drop_probability {
+            return deliveries;
+        }
+        let copies = if self.rng.gen::<f64>() < self.profile.duplicate_probability { 2 } else { 1 };
+        for _i in 0..copies {
+            let mut delay = self.profile.delay.sample(&mut self.rng);
+            let reordered = self.rng.gen::<f64>() < self.profile.reorder_probability;
+            if reordered {
+                delay += self.profile.reorder_hold;
+            }
+            deliveries.push((delay, reordered));
+        }
+        deliveries
+    }
+}
+
+struct Pending {
+    /// Delivery time, measured from the start of the link
+    due: Duration,
+    /// Breaks ties between messages due at the same instant, preserving arrival order
+    seq: u64,
+    header: UorbHeader,
+    msg: UorbMessage,
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        self.due == other.due && self.seq == other.seq
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.due, self.seq).cmp(&(other.due, other.seq))
+    }
+}
+
+struct DelayQueue {
+    heap: BinaryHeap<Reverse<Pending>>,
+    /// Due time of the latest in-order message, so jitter alone never reorders
+    last_due: Option<Duration>,
+    seq: u64,
+    /// Set when the connection is dropped, or when the underlying connection fails
+    closed: bool,
+    failure: Option<(io::ErrorKind, String)>,
+}
+
+impl DelayQueue {
+    fn new() -> Self {
+        DelayQueue {
+            heap: BinaryHeap::new(),
+            last_due: None,
+            seq: 0,
+            closed: false,
+            failure: None,
+        }
+    }
+
+    /// Queue the deliveries of one message sent at `now`
+    fn push(&mut self, now: Duration, deliveries: Vec<(Duration, bool)>, header: &UorbHeader, msg: &UorbMessage) {
+        for (delay, reordered) in deliveries {
+            let mut due = now + delay;
+            if !reordered {
+                if let Some(last_due) = self.last_due {
+                    if due < last_due {
+                        due = last_due;
+                    }
+                }
+                self.last_due = Some(due);
+            }
+            self.seq += 1;
+            self.heap.push(Reverse(Pending {
+                due,
+                seq: self.seq,
+                header: header.clone(),
+                msg: msg.clone(),
+            }));
+        }
+    }
+
+    fn next_due(&self) -> Option<Duration> {
+        self.heap.peek().map(|head| (head.0).due)
+    }
+
+    /// The next message, if it has fallen due by `now`
+    fn pop_due(&mut self, now: Duration) -> Option<(UorbHeader, UorbMessage)> {
+        match self.next_due() {
+            Some(due) if due <= now => self.heap.pop().map(|Reverse(pending)| (pending.header, pending.msg)),
+            _ => None,
+        }
+    }
+}
+
+/// The impairments of one direction of a link, driven by an explicit clock rather than wall-clock time.
+///
+/// `ImpairedConnection` runs the same model against real time in its pump threads:
+/// driving it directly gives reproducible delivery schedules, eg in simulated time or in tests.
+pub struct LinkModel {
+    impairer: Impairer,
+    queue: DelayQueue,
+}
+
+impl LinkModel {
+    pub fn new(profile: ImpairmentProfile, seed: u64) -> Self {
+        LinkModel {
+            impairer: Impairer::new(profile, seed),
+            queue: DelayQueue::new(),
+        }
+    }
+
+    /// Send a message at `now`, measured from the start of the link
+    pub fn send(&mut self, now: Duration, header: &UorbHeader, msg: &UorbMessage) {
+        let deliveries = self.impairer.schedule();
+        self.queue.push(now, deliveries, header, msg);
+    }
+
+    /// When the next pending message falls due, if any are pending
+    pub fn next_due(&self) -> Option<Duration> {
+        self.queue.next_due()
+    }
+
+    /// Every message due for delivery by `now`, in delivery order
+    pub fn deliver(&mut self, now: Duration) -> Vec<(UorbHeader, UorbMessage)> {
+        let mut delivered = vec![];
+        while let Some(item) = self.queue.pop_due(now) {
+            delivered.push(item);
+        }
+        delivered
+    }
+}
+
+enum Pop {
+    Item(UorbHeader, UorbMessage),
+    TimedOut,
+    Closed,
+}
+
+/// Holds messages until their scheduled delivery time, on the wall clock
+struct DelayLine {
+    queue: Mutex<DelayQueue>,
+    changed: Condvar,
+    /// Due times are measured from here
+    start: Instant,
+}
+
+impl DelayLine {
+    fn new() -> Self {
+        DelayLine {
+            queue: Mutex::new(DelayQueue::new()),
+            changed: Condvar::new(),
+            start: Instant::now(),
+        }
+    }
+
+    fn push(&self, deliveries: Vec<(Duration, bool)>, header: &UorbHeader, msg: &UorbMessage) -> io::Result<()> {
+        if deliveries.is_empty() {
+            return Ok(());
+        }
+        let now = self.start.elapsed();
+        let mut queue = self.queue.lock().map_err(poisoned)?;
+        queue.push(now, deliveries, header, msg);
+        self.changed.notify_all();
+        Ok(())
+    }
+
+    /// Wait for the next message to fall due, giving up after `timeout` if one is set
+    fn pop(&self, timeout: Option<Duration>) -> io::Result<Pop> {
+        let deadline = timeout.map(|timeout| self.start.elapsed() + timeout);
+        let mut queue = self.queue.lock().map_err(poisoned)?;
+        loop {
+            let now = self.start.elapsed();
+            if let Some((header, msg)) = queue.pop_due(now) {
+                return Ok(Pop::Item(header, msg));
+            }
+            let head_due = queue.next_due();
+            if queue.closed && head_due.is_none() {
+                return Ok(Pop::Closed);
+            }
+            if let Some(deadline) = deadline {
+                if deadline <= now {
+                    return Ok(Pop::TimedOut);
+                }
+            }
+
+            let wake_at = match (head_due, deadline) {
+                (Some(due), Some(deadline)) => Some(std::cmp::min(due, deadline)),
+                (Some(due), None) => Some(due),
+                (None, deadline) => deadline,
+            };
+            queue = match wake_at {
+                Some(wake_at) => self.changed.wait_timeout(queue, wake_at - now).map_err(poisoned)?.0,
+                None => self.changed.wait(queue).map_err(poisoned)?,
+            };
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.queue.lock().map(|queue| queue.closed).unwrap_or(true)
+    }
+
+    fn close(&self, failure: Option<&io::Error>) {
+        // closing only sets flags, so it must still wake any waiters after a panic elsewhere
+        let mut queue = self.queue.lock().unwrap_or_else(PoisonError::into_inner);
+        queue.closed = true;
+        if queue.failure.is_none() {
+            queue.failure = failure.map(|e| (e.kind(), e.to_string()));
+        }
+        self.changed.notify_all();
+    }
+
+    fn failure(&self) -> io::Result<Option<io::Error>> {
+        let queue = self.queue.lock().map_err(poisoned)?;
+        Ok(queue.failure.as_ref().map(|(kind, text)| io::Error::new(*kind, text.clone())))
+    }
+}
+
+/// Decorates a connection with a degraded link: delay, jitter, loss, duplication and reordering.
+///
+/// Each direction is driven by its own pump thread, so delayed messages are delivered
+/// on schedule without blocking the caller. An error on the underlying connection
+/// is reported by the next `send` or `recv`.
+///
+/// The inbound pump polls with `try_recv`, so it exits soon after the connection is dropped
+/// for transports with a native non-blocking receive, and within the read timeout for the rest.
+pub struct ImpairedConnection {
+    send_line: Arc<DelayLine>,
+    recv_line: Arc<DelayLine>,
+    send_impairer: Mutex<Impairer>,
+}
+
+impl ImpairedConnection {
+    pub fn new<C>(inner: C, config: ImpairmentConfig) -> Self
+        where C: UorbConnection + Send + Sync + 'static {
+        let inner = Arc::new(inner);
+        let send_line = Arc::new(DelayLine::new());
+        let recv_line = Arc::new(DelayLine::new());
+
+        // outbound pump: deliver each sent message once it falls due
+        thread::spawn({
+            let inner = inner.clone();
+            let send_line = send_line.clone();
+            move || {
+                while let Ok(Pop::Item(header, msg)) = send_line.pop(None) {
+                    if let Err(e) = inner.send(&header, &msg) {
+                        send_line.close(Some(&e));
+                        break;
+                    }
+                }
+            }
+        });
+
+        // inbound pump: impair each received message on arrival.
+        // It polls, rather than blocking in recv, so that it notices promptly when the connection is dropped.
+        thread::spawn({
+            let recv_line = recv_line.clone();
+            let mut impairer = Impairer::new(config.recv.clone(), config.seed.wrapping_add(1));
+            move || {
+                while !recv_line.is_closed() {
+                    match inner.try_recv() {
+                        Ok(Some((header, msg))) => {
+                            if recv_line.push(impairer.schedule(), &header, &msg).is_err() {
+                                break;
+                            }
+                        },
+                        Ok(None) => thread::sleep(PUMP_POLL_INTERVAL),
+                        Err(ref e) if is_timeout(e) => continue,
+                        Err(e) => {
+                            recv_line.close(Some(&e));
+                            break;
+                        },
+                    }
+                }
+            }
+        });
+
+        ImpairedConnection {
+            send_line,
+            recv_line,
+            send_impairer: Mutex::new(Impairer::new(config.send, config.seed)),
+        }
+    }
+}
+
+impl Drop for ImpairedConnection {
+    fn drop(&mut self) {
+        // lets both pump threads wind down once any pending messages are flushed
+        self.send_line.close(None);
+        self.recv_line.close(None);
+    }
+}
+
+impl UorbConnection for ImpairedConnection {
+    fn recv(&self) -> io::Result<(UorbHeader, UorbMessage)> {
+        match self.recv_line.pop(Some(RECV_TIMEOUT))? {
+            Pop::Item(header, msg) => Ok((header, msg)),
+            Pop::TimedOut => Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "No messages due",
+            )),
+            Pop::Closed => Err(self.recv_line.failure()?.unwrap_or_else(|| io::Error::new(
+                io::ErrorKind::NotConnected,
+                "Connection closed",
+            ))),
+        }
+    }
+
+    fn try_recv(&self) -> io::Result<Option<(UorbHeader, UorbMessage)>> {
+        match self.recv_line.pop(Some(Duration::from_secs(0)))? {
+            Pop::Item(header, msg) => Ok(Some((header, msg))),
+            Pop::TimedOut => Ok(None),
+            Pop::Closed => Err(self.recv_line.failure()?.unwrap_or_else(|| io::Error::new(
+                io::ErrorKind::NotConnected,
+                "Connection closed",
+            ))),
+        }
+    }
+
+    fn send(&self, header: &UorbHeader, data: &UorbMessage) -> io::Result<()> {
+        if let Some(e) = self.send_line.failure()? {
+            return Err(e);
+        }
+        let deliveries = self.send_impairer.lock().map_err(poisoned)?.schedule();
+        self.send_line.push(deliveries, header, data)
+    }
+}
@@ -6,7 +6,44 @@ pub mod connection;
 /// Reports the vehicle's simulated physical state back to the mav firmware
 pub mod mav_writer;
 
+/// Follows simulated time across updates, detecting simulator restarts
+pub mod sim_clock;
+
+/// Tracks the simulated vehicle's attitude quaternion
+pub mod attitude;
+
 /// Receives state updates from the mav firmware and forwards to physical simulator
 pub mod mav_reader;
 
+/// Maps the mav firmware's actuator outputs onto the simulated vehicle's controls
+pub mod airframe;
+
+/// Discharges the simulated battery by the load on the rotors
+pub mod battery;
+
+/// Injects realistic GNSS errors into the simulated GPS
+pub mod gps;
+
+/// UTC time for the simulated GPS, counted from a configurable epoch
+pub mod gps_time;
+
+/// Relates altitude above mean sea level to altitude above the WGS84 ellipsoid
+pub mod geoid;
+
+/// Per-channel PWM endpoints, loaded from PX4 parameters
+pub mod calibration;
+
+/// Captures the traffic crossing a connection to a session log, and plays it back
+pub mod recording;
+
+/// Simulates a degraded link between the simulator and the mav firmware
+pub mod impairment;
+
+/// Counts the traffic crossing a connection, per topic and direction
+pub mod stats;
+
+/// Filters the simulator's outbound topics and fans them out to one or more connections
+pub mod router;
 
+/// Random variates shared by the simulated errors and impairments
+mod random;
@@ -1,48 +1,305 @@
 
+use std::error::Error;
 use std::sync::{Arc, RwLock};
 use std::thread;
+use std::time::Duration;
 
 use mavulator::*;
 
-use connection::UorbConnection;
+use airframe::Airframe;
+use battery::{BatteryConfig, BatteryModel};
+use calibration::PwmCalibration;
+use geoid::Geoid;
+use gps::{FixOutage, GpsErrorConfig, GpsErrorModel};
+use gps_time::{GpsClock, SimulationEpoch, UtcDateTime};
+use connection::{Backoff, ConnectionError, ConnectionSpec, Protocol, UorbConnection};
+use mav_reader::{InputMode, SharedControls};
+use router::{Router, Rule, TopicFilter, TOPIC_NAMES};
+use stats::StatsConnection;
 
 use flighty::physical_types::GlobalPosition;
 use flighty::simulato::Simulato;
 
+/// Where the px4_sitl sidecar listens, unless `--connect` says otherwise
+const DEFAULT_CONNECT_SELECTOR: &str = "tcpout:127.0.0.1:4560";
+
+/// EGM96 geoid height above the WGS84 ellipsoid at the simulated home location, in meters
+const HOME_GEOID_UNDULATION: f32 = -32.0;
+
+/// The value following `flag` on the command line, if the flag is present
+fn arg_value(flag: &str) -> Option<String> {
+    let args: Vec<String> = std::env::args().collect();
+    args.iter().position(|arg| arg == flag)
+        .map(|idx| args.get(idx + 1).cloned().unwrap_or_default())
+}
+
+/// Build the airframe from `--airframe <name>` (default quad-x),
+/// `--reversible-rotors <deadband_us>` (default off)
+/// and `--pwm-params <file>` (default 1000..2000us endpoints)
+fn airframe_from_args() -> Result<Airframe, Box<dyn Error>> {
+    let mut airframe = match arg_value("--airframe") {
+        Some(name) => Airframe::preset(&name)?,
+        None => Airframe::default(),
+    };
+    if let Some(deadband) = arg_value("--reversible-rotors") {
+        let deadband: f32 = deadband.parse()
+            .map_err(|_e| format!("invalid reversible rotor deadband '{}'", deadband))?;
+        airframe = airframe.with_reversible_rotors(deadband);
+    }
+    match arg_value("--pwm-params") {
+        Some(path) => Ok(airframe.with_calibration(PwmCalibration::load(path)?)),
+        None => Ok(airframe),
+    }
+}
+
+/// Select the input with `--input outputs` (the default: mixer outputs through the airframe)
+/// or `--input controls` (actuator_controls groups, passed straight through to the plane)
+fn input_mode_from_args() -> Result<InputMode, Box<dyn Error>> {
+    match arg_value("--input").as_ref().map(|mode| mode.as_str()) {
+        None | Some("outputs") => Ok(InputMode::MixerOutputs(airframe_from_args()?)),
+        Some("controls") => {
+            // controls never pass through PWM, so the PWM mapping flags would be silently ignored
+            for flag in &["--pwm-params", "--reversible-rotors"] {
+                if arg_value(flag).is_some() {
+                    return Err(format!("{} only applies to --input outputs", flag).into());
+                }
+            }
+            let airframe = Airframe::preset(&arg_value("--airframe").unwrap_or_else(|| "plane".to_string()))?;
+            Ok(InputMode::actuator_controls_for(&airframe)?)
+        }
+        Some(mode) => Err(format!("unknown input mode '{}', expected outputs or controls", mode).into()),
+    }
+}
+
+/// Size the battery pack with `--battery-capacity <mAh>` and `--battery-cells <n>` (default 12000mAh 4S)
+fn battery_config_from_args() -> Result<BatteryConfig, Box<dyn Error>> {
+    let mut config = BatteryConfig::default();
+    if let Some(capacity) = arg_value("--battery-capacity") {
+        config.capacity_mah = capacity.parse().ok()
+            .filter(|capacity_mah: &f32| *capacity_mah > 0.0 && *capacity_mah <= std::u16::MAX as f32)
+            .ok_or_else(|| format!("invalid battery capacity '{}'", capacity))?;
+    }
+    if let Some(cells) = arg_value("--battery-cells") {
+        config.cell_count = cells.parse().ok()
+            .filter(|cell_count: &u8| *cell_count > 0)
+            .ok_or_else(|| format!("invalid battery cell count '{}'", cells))?;
+    }
+    Ok(config)
+}
+
+/// A scheduled GPS outage given as `<start_s>:<duration_s>`
+fn parse_fix_outage(outage: &str) -> Result<FixOutage, Box<dyn Error>> {
+    let secs: Vec<f64> = outage.split(':')
+        .map(|val| val.parse::<f64>().ok().filter(|secs| secs.is_finite() && *secs >= 0.0))
+        .collect::<Option<Vec<f64>>>()
+        .filter(|secs| secs.len() == 2)
+        .ok_or_else(|| format!("invalid GPS outage '{}', expected <start_s>:<duration_s>", outage))?;
+    Ok(FixOutage {
+        start: Duration::from_secs_f64(secs[0]),
+        duration: Duration::from_secs_f64(secs[1]),
+    })
+}
+
+/// Configure GPS errors with `--gps-errors ideal|typical` (default ideal),
+/// `--gps-seed <n>` and `--gps-outages <start_s>:<duration_s>[,...]`
+fn gps_config_from_args() -> Result<GpsErrorConfig, Box<dyn Error>> {
+    let mut config = match arg_value("--gps-errors").as_ref().map(|errors| errors.as_str()) {
+        None | Some("ideal") => GpsErrorConfig::default(),
+        Some("typical") => GpsErrorConfig::typical(),
+        Some(errors) => return Err(format!("unknown GPS errors '{}', expected ideal or typical", errors).into()),
+    };
+    if let Some(seed) = arg_value("--gps-seed") {
+        config.seed = seed.parse().map_err(|_e| format!("invalid GPS seed '{}'", seed))?;
+    }
+    if let Some(outages) = arg_value("--gps-outages") {
+        config.outages = outages.split(',')
+            .map(parse_fix_outage)
+            .collect::<Result<Vec<FixOutage>, Box<dyn Error>>>()?;
+    }
+    Ok(config)
+}
+
+/// The UTC time when simulated time starts, from `--gps-epoch now|<YYYY-MM-DDTHH:MM:SSZ>|<unix_s>` (default now)
+fn gps_epoch_from_args() -> Result<SimulationEpoch, Box<dyn Error>> {
+    match arg_value("--gps-epoch") {
+        Some(epoch) => Ok(epoch.parse()?),
+        None => Ok(SimulationEpoch::Now),
+    }
+}
+
+/// Mean sea level at the home location, from `--geoid-undulation <m>`:
+/// the EGM96 geoid height above the WGS84 ellipsoid (default: the value at the home location)
+fn geoid_from_args() -> Result<Geoid, Box<dyn Error>> {
+    match arg_value("--geoid-undulation") {
+        Some(undulation) => {
+            let undulation: f32 = undulation.parse().ok()
+                .filter(|undulation: &f32| undulation.is_finite())
+                .ok_or_else(|| format!("invalid geoid undulation '{}'", undulation))?;
+            Ok(Geoid::new(undulation))
+        },
+        None => Ok(Geoid::new(HOME_GEOID_UNDULATION)),
+    }
+}
+
+/// The connection to the mav firmware, from `--connect <selector>` (default tcpout:127.0.0.1:4560)
+fn connection_spec_from_args() -> Result<ConnectionSpec, Box<dyn Error>> {
+    let selector = arg_value("--connect").unwrap_or_else(|| DEFAULT_CONNECT_SELECTOR.to_string());
+    Ok(selector.parse()?)
+}
+
+/// How often to print traffic statistics, from `--stats-interval <s>` (default never)
+fn stats_interval_from_args() -> Result<Option<Duration>, Box<dyn Error>> {
+    match arg_value("--stats-interval") {
+        Some(secs) => {
+            let secs: f64 = secs.parse().ok()
+                .filter(|secs: &f64| secs.is_finite() && *secs > 0.0)
+                .ok_or_else(|| format!("invalid stats interval '{}'", secs))?;
+            Ok(Some(Duration::from_secs_f64(secs)))
+        },
+        None => Ok(None),
+    }
+}
+
+/// Topics to suppress, from `--deny-topics <topic>[:<instance>][,...]`, eg `DifferentialPressure` on a multirotor
+fn topic_filter_from_args() -> Result<TopicFilter, Box<dyn Error>> {
+    let mut filter = TopicFilter::allow_all();
+    if let Some(topics) = arg_value("--deny-topics") {
+        for topic in topics.split(',') {
+            let mut parts = topic.splitn(2, ':');
+            let name = parts.next().unwrap_or_default();
+            if !TOPIC_NAMES.contains(&name) {
+                return Err(format!("unknown topic '{}', expected one of {:?}", name, TOPIC_NAMES).into());
+            }
+            let rule = match parts.next() {
+                Some(instance) => Rule::deny(name).instance(instance.parse()
+                    .map_err(|_e| format!("invalid topic instance in '{}'", topic))?),
+                None => Rule::deny(name),
+            };
+            filter = filter.rule(rule);
+        }
+    }
+    Ok(filter)
+}
+
+/// Everything configurable from the command line
+struct Config {
+    connection_spec: ConnectionSpec,
+    input_mode: InputMode,
+    battery: BatteryConfig,
+    gps: GpsErrorConfig,
+    gps_epoch: SimulationEpoch,
+    geoid: Geoid,
+    stats_interval: Option<Duration>,
+    topic_filter: TopicFilter,
+}
+
+fn config_from_args() -> Result<Config, Box<dyn Error>> {
+    Ok(Config {
+        connection_spec: connection_spec_from_args()?,
+        input_mode: input_mode_from_args()?,
+        battery: battery_config_from_args()?,
+        gps: gps_config_from_args()?,
+        gps_epoch: gps_epoch_from_args()?,
+        geoid: geoid_from_args()?,
+        stats_interval: stats_interval_from_args()?,
+        topic_filter: topic_filter_from_args()?,
+    })
+}
+
+/// Open the connection to the mav firmware, then create the simulator.
+///
+/// A `tcpout` connection reconnects if the sidecar restarts, re-establishing the time base
+/// before sending any sensor data; other transports are opened once.
+fn connect(spec: &ConnectionSpec, home: &GlobalPosition)
+    -> Result<(Box<dyn UorbConnection + Send + Sync>, Arc<RwLock<Simulato>>), ConnectionError> {
+    if Protocol::TcpOut != spec.protocol {
+        let conn = spec.open()?;
+        //don't create the shared state object until after we've connected
+        return Ok((conn, Arc::new(RwLock::new(Simulato::new(home)))));
+    }
+
+    let mut conn = spec.open_reconnecting(Backoff::default())?;
+    let shared_sim:Arc<RwLock<Simulato>> = Arc::new(RwLock::new(Simulato::new(home)));
+    conn.set_on_connect({
+        let sim = shared_sim.clone();
+        move |c: &dyn UorbConnection| mav_writer::send_timesync(&sim, c)
+    });
+    conn.set_state_listener(|state| println!("connection: {:?}", state));
+    Ok((Box::new(conn), shared_sim))
+}
+
 fn main() {
-    println!("starting");
+    let config = match config_from_args() {
+        Ok(config) => config,
+        Err(e) => {
+            println!("{}...terminating", e);
+            return;
+        }
+    };
+    let input_mode = config.input_mode;
+    match input_mode {
+        InputMode::MixerOutputs(ref airframe) => println!("starting with airframe {}", airframe.name),
+        InputMode::ActuatorControls => println!("starting with actuator controls input"),
+    }
     let home = GlobalPosition {
         lat: 37.8001024,
         lon: -122.1997184,
         alt_wgs84: 10.0
     };
 
-    let selector = "tcpout:127.0.0.1:4560";
-    //let selector = "tcpout:rock64-04.local:4560";
-    let conn = connection::select_protocol(selector);
-    if !conn.is_ok() {
-        println!("Couldn't connect...terminating");
-        return;
+    let (conn, shared_sim) = match connect(&config.connection_spec, &home) {
+        Ok(connected) => connected,
+        Err(e) => {
+            println!("Couldn't connect ({})...terminating", e);
+            return;
+        }
+    };
+    println!("connected");
+
+    let conn = StatsConnection::new(conn);
+    let traffic_stats = conn.handle();
+    let vehicle_conn: Arc<dyn UorbConnection + Send + Sync> = Arc::new(conn);
+
+    // periodically report how much traffic is crossing the link
+    if let Some(interval) = config.stats_interval {
+        thread::spawn(move || {
+            loop {
+                thread::sleep(interval);
+                println!("{}", traffic_stats.snapshot());
+            }
+        });
     }
 
-    let vehicle_conn = Arc::new(conn.unwrap());
-    println!("connected");
+    // the controls applied by the reader load the battery reported by the writer
+    let last_controls: SharedControls = SharedControls::default();
+
+    // read the wall clock for an epoch of "now" only once the simulation is about to start
+    let gps_clock = GpsClock::new(config.gps_epoch);
+    println!("GPS time starts at {}", UtcDateTime::from_unix_micros(gps_clock.epoch_micros()));
 
-    //don't create the shared state object until after we've connected
-    let shared_sim:Arc<RwLock<Simulato>> = Arc::new(RwLock::new(Simulato::new(&home)));
+    // the generated topics pass through the router's filter on their way to the mav firmware
+    let mut router = Router::new();
+    router.add_route(vehicle_conn.clone(), config.topic_filter);
 
     // start a thread reporting the vehicle's simulated physical state back to the mav firmware
     thread::spawn({
-        let conn:Arc<Box<UorbConnection+Send+Sync>> = vehicle_conn.clone();
         let sim = shared_sim.clone();
+        let reporting = mav_writer::ReportingState {
+            battery: BatteryModel::new(config.battery, input_mode.load_controls()),
+            gps: GpsErrorModel::new(config.gps),
+            gps_clock,
+            geoid: config.geoid,
+            controls: last_controls.clone(),
+            ..Default::default()
+        };
         move || {
-            mav_writer::reporting_loop(sim, conn);
+            mav_writer::reporting_loop(sim, router, reporting);
         }
     });
 
     // this thread loops forever receiving state messages from the mav firmware and
     // forwarding to the physical simulator
-    mav_reader::feedback_loop(shared_sim, vehicle_conn);
+    mav_reader::feedback_loop(shared_sim, vehicle_conn, input_mode, last_controls);
 
 }
 
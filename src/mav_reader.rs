@@ -1,97 +1,149 @@
 use uorb_codec::{self, UorbHeader, UorbMessage};
 use std::sync::{Arc, RwLock};
-use std::thread;
-use std::time::{Duration};
 
 use flighty::models::ActuatorControls;
 use flighty::simulato::Simulato;
 
 use uorb_codec::common::*;
 
-use crate::connection::UorbConnection;
+use crate::airframe::{Airframe, AirframeError};
+use crate::connection::{is_timeout, UorbConnection};
 
 
 
+/// The latest controls applied to the simulated vehicle, shared with the reporting side
+pub type SharedControls = Arc<RwLock<ActuatorControls>>;
+
+/// Apply PWM outputs to the simulated vehicle, returning the controls applied
 pub fn handle_actuator_outputs(shared_simulato:Arc<RwLock<Simulato>>,
+                               airframe: &Airframe,
                                _header: &UorbHeader,
                                data: &ActuatorOutputsData
-) {
-    let controls = normalize_actuator_outputs(&data.output, 4);
+) -> ActuatorControls {
+    let controls = airframe.map_outputs(&data.output);
     let mut state_w = shared_simulato.write().unwrap();
     //println!("msg time: {}", header.timestamp);
     state_w.update( &controls);
+    controls
 }
 
-///  Default minimum PWM in microseconds
-const PWM_DEFAULT_MIN: f32 = 1000.0;
-const PWM_DEFAULT_MIN_HALF: f32 = PWM_DEFAULT_MIN / 2.0;
+/// Map PWM outputs with the first `nrotors` channels as rotors, and the rest as surfaces
+pub fn normalize_actuator_outputs(pwm: &[f32; 16], nrotors: usize) -> ActuatorControls {
+    Airframe::multirotor("custom", nrotors).map_outputs(pwm)
+}
 
-/// Default maximum PWM in microseconds
-const PWM_DEFAULT_MAX: f32 = 2000.0;
+/// Number of controls in each PX4 actuator_controls group
+pub const CONTROLS_PER_GROUP: usize = 8;
 
-const PWM_DEFAULT_RANGE: f32 = (PWM_DEFAULT_MAX - PWM_DEFAULT_MIN);
-const PWM_DEFAULT_RANGE_HALF: f32 = PWM_DEFAULT_RANGE / 2.0;
+/// Index of the thrust control in actuator_controls group 0, as per PX4 `INDEX_THROTTLE`
+pub const GROUP0_THRUST_CONTROL: usize = 3;
 
-const PWM_DEFAULT_CENTER: f32  = (PWM_DEFAULT_MAX + PWM_DEFAULT_MIN) / 2.0;
+/// Copy one actuator_controls group into its slot of the flighty controls:
+/// group 0 fills controls 0..8 and group 1 fills 8..16. Unset (NaN) controls become zero.
+pub fn merge_control_group(ctrls: &mut ActuatorControls, group: usize, control: &[f32; CONTROLS_PER_GROUP]) {
+    let offset = group * CONTROLS_PER_GROUP;
+    for (i, val) in control.iter().enumerate() {
+        if let Some(ctrl) = ctrls.get_mut(offset + i) {
+            *ctrl = if val.is_nan() { 0.0 } else { *val };
+        }
+    }
+}
 
+/// Which topics from the mav firmware drive the simulated vehicle
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputMode {
+    /// PWM `ActuatorOutputs` from the mixer, mapped through an airframe
+    MixerOutputs(Airframe),
+    /// Normalized `actuator_controls_0` and `actuator_controls_1` groups, skipping the mixer and PWM
+    ActuatorControls,
+}
 
-pub fn normalize_actuator_outputs(pwm: &[f32; 16], nrotors: usize) -> ActuatorControls {
-    let mut ctrls:ActuatorControls = [0.0; 16];
-    //println!("pwm: {:?}",pwm);
-    for i in 0..16 {
-        let pwm_val = pwm[i];
-        if !pwm_val.is_nan() &&  pwm_val > PWM_DEFAULT_MIN_HALF {
-            if i < nrotors {
-                // scale  to 0..1 for rotors
-                //TODO what about reversible rotors??
-                ctrls[i] = (pwm_val - PWM_DEFAULT_MIN) / PWM_DEFAULT_RANGE;
-                if ctrls[i] < 0.0 {
-                    ctrls[i] = 0.0;
-                }
-            }
-            else {
-                // scale to -1..1 for non-rotor channels
-                ctrls[i] = (pwm_val - PWM_DEFAULT_CENTER) / PWM_DEFAULT_RANGE_HALF;
-            }
+impl InputMode {
+    /// Drive `airframe` directly by actuator_controls groups.
+    ///
+    /// Group 0 is roll, pitch, yaw and thrust, passed through without mixing, so this only suits
+    /// an airframe whose one rotor takes the thrust control, such as the standard plane.
+    /// A multirotor needs the mixer to spread those controls over its rotors.
+    pub fn actuator_controls_for(airframe: &Airframe) -> Result<InputMode, AirframeError> {
+        if airframe.rotor_controls() != [GROUP0_THRUST_CONTROL] {
+            return Err(AirframeError::NeedsMixer(airframe.name.clone()));
+        }
+        Ok(InputMode::ActuatorControls)
+    }
+
+    /// The controls that drive rotors, and so draw current from the battery
+    pub fn load_controls(&self) -> Vec<usize> {
+        match self {
+            InputMode::MixerOutputs(airframe) => airframe.rotor_controls(),
+            InputMode::ActuatorControls => vec![GROUP0_THRUST_CONTROL],
         }
     }
-    //println!("ctrls {:?}", ctrls);
-    ctrls
 }
 
+impl Default for InputMode {
+    fn default() -> Self {
+        InputMode::MixerOutputs(Airframe::default())
+    }
+}
+
+
+pub fn feedback_loop(shared_simulato:Arc<RwLock<Simulato>>,
+                     vehicle_conn: Arc<dyn UorbConnection + Send + Sync>,
+                     input_mode: InputMode,
+                     last_controls: SharedControls) {
+    // the latest value of both control groups, as each group arrives separately
+    let mut group_controls:ActuatorControls = [0.0; 16];
 
-pub fn feedback_loop(shared_simulato:Arc<RwLock<Simulato>>, vehicle_conn:Arc<Box<UorbConnection+Send+Sync>>) {
     // loop receiving messages from the mav firmware itself
     loop {
         match vehicle_conn.recv() {
-            Ok((_header, msg)) => {
-                match msg {
-                    UorbMessage::ActuatorOutputs(m) => {
-                        let controls = normalize_actuator_outputs(&m.output, 4);
-                        let mut state_w = shared_simulato.write().unwrap();
-                        //println!("msg time: {}", header.timestamp);
-                        state_w.update(&controls);
+            Ok((header, msg)) => {
+                let applied = match (&input_mode, msg) {
+                    (InputMode::MixerOutputs(airframe), UorbMessage::ActuatorOutputs(m)) => {
+                        Some(handle_actuator_outputs(shared_simulato.clone(), airframe, &header, &m))
                     },
-                    UorbMessage::VehicleStatus(_m) => {
+                    (InputMode::ActuatorControls, UorbMessage::ActuatorControls0(m)) => {
+                        merge_control_group(&mut group_controls, 0, &m.control);
+                        shared_simulato.write().unwrap().update(&group_controls);
+                        Some(group_controls)
+                    },
+                    (InputMode::ActuatorControls, UorbMessage::ActuatorControls1(m)) => {
+                        merge_control_group(&mut group_controls, 1, &m.control);
+                        shared_simulato.write().unwrap().update(&group_controls);
+                        Some(group_controls)
+                    },
+                    (_, UorbMessage::ActuatorOutputs(_)) |
+                    (_, UorbMessage::ActuatorControls0(_)) |
+                    (_, UorbMessage::ActuatorControls1(_)) => {
+                        // not the input selected for this run
+                        None
+                    },
+                    (_, UorbMessage::VehicleStatus(_m)) => {
                         //TODO provide to simulato?
+                        None
                     },
-                    _ => {
+                    (_, msg) => {
                         println!("recv: {:?}", msg);
+                        None
                     }
+                };
+                if let Some(controls) = applied {
+                    *last_controls.write().unwrap() = controls;
                 }
             },
             Err(e) => {
-                match e.kind() {
-                    std::io::ErrorKind::WouldBlock => {
-                        //no messages currently available to receive -- wait a while
-                        thread::sleep(Duration::from_secs(1));
-                        continue;
-                    },
-                    _ => {
-                        println!("recv error: {:?}", e);
-                        break;
-                    }
+                if is_timeout(&e) {
+                    // recv already waited out its read timeout: go straight back to
+                    // waiting, so the next actuator output is handled as soon as it arrives
+                    continue;
+                }
+                if e.kind() == std::io::ErrorKind::InvalidData {
+                    // one undecodable message doesn't make the link unusable
+                    println!("recv dropped invalid message: {:?}", e);
+                    continue;
                 }
+                println!("recv error: {:?}", e);
+                break;
             }
         }
     }
@@ -3,13 +3,20 @@ use std::f32::NAN;
 
 use std::sync::{Arc, RwLock};
 use std::thread;
-use std::io::Error;
+use std::io::{Error, ErrorKind};
 use std::time::{Duration};
 
 use uorb_codec::common::*;
 use uorb_codec::{self, UorbHeader, UorbMessage, UorbMsgMeta};
 
+use crate::attitude::AttitudeTracker;
+use crate::battery::BatteryModel;
 use crate::connection::UorbConnection;
+use crate::geoid::Geoid;
+use crate::gps::{offset_lat_lon, GpsErrorModel, GpsFix};
+use crate::gps_time::GpsClock;
+use crate::mav_reader::SharedControls;
+use crate::router::Router;
 
 use flighty::simulato::Simulato;
 use flighty::physical_types::*;
@@ -27,13 +34,29 @@ const MEDIUM_CADENCE_MICROSECONDS: TimeBaseUnits = 10000;
 /// Timing for slow cadence sensors: 5Hz approximately
 const SLOW_CADENCE_MICROSECONDS: TimeBaseUnits = 200000;
 
+/// How often to retry the first send while the link has no peer yet
+const PEER_WAIT_INTERVAL: Duration = Duration::from_millis(10);
+
+
+/// State carried from one call of `collect_messages` to the next
+#[derive(Debug, Clone, Default)]
+pub struct ReportingState {
+    pub last_slow_cadence_send: TimeBaseUnits, //1Hz sensors
+    pub last_med_cadence_send: TimeBaseUnits, //100Hz sensors
+    pub last_fast_cadence_send: TimeBaseUnits, //400Hz sensors
+    pub attitude: AttitudeTracker,
+    pub battery: BatteryModel,
+    pub gps: GpsErrorModel,
+    pub gps_clock: GpsClock,
+    /// The datum for altitude above mean sea level, shared by GPS and baro
+    pub geoid: Geoid,
+    /// The latest controls applied by the mav_reader, which load the battery
+    pub controls: SharedControls,
+}
 
 //TODO collect_messages shouldn't really be pub , but is required for benchmarking?
 pub fn collect_messages(sim: &Arc<RwLock<Simulato>>,
-                    last_slow_cadence_send: &mut TimeBaseUnits,
-                    last_med_cadence_send: &mut TimeBaseUnits,
-                    last_fast_cadence_send: &mut TimeBaseUnits
-
+                        reporting: &mut ReportingState
 ) -> Vec<(UorbHeader, UorbMessage)> {
 
     let mut msg_list: Vec<(UorbHeader, UorbMessage)> = vec![];
@@ -47,30 +70,34 @@ pub fn collect_messages(sim: &Arc<RwLock<Simulato>>,
         }
 
         let state_r = sim.read().unwrap();
+        let orientation = &state_r.vehicle_state.kinematic.orientation;
+        reporting.attitude.update(time_check, [orientation[0], orientation[1], orientation[2], orientation[3]]);
+        reporting.battery.update(time_check, &reporting.controls.read().unwrap());
         //let sensed_z = state_r.sensed.accel.get_val()[2];
         //println!("time {}  sensed accel_z {}", time_check, sensed_z );
 
-        if (0 ==  *last_fast_cadence_send) ||
-            (state_r.elapsed_since(*last_fast_cadence_send) > FAST_CADENCE_MICROSECONDS) {
+        if (0 ==  reporting.last_fast_cadence_send) ||
+            (state_r.elapsed_since(reporting.last_fast_cadence_send) > FAST_CADENCE_MICROSECONDS) {
             let msgs = collect_fast_cadence_sensors(&state_r);
             msg_list.extend(msgs);
-            *last_fast_cadence_send = time_check;
+            reporting.last_fast_cadence_send = time_check;
         }
 
         // Medium cadence: about 100Hz  10000 usec
-        if (0 ==  *last_med_cadence_send) ||
-            (state_r.elapsed_since(*last_med_cadence_send) > MEDIUM_CADENCE_MICROSECONDS) {
-            let msgs = collect_med_cadence_sensors(&state_r);
+        if (0 ==  reporting.last_med_cadence_send) ||
+            (state_r.elapsed_since(reporting.last_med_cadence_send) > MEDIUM_CADENCE_MICROSECONDS) {
+            let msgs = collect_med_cadence_sensors(&state_r, &reporting.attitude, &reporting.geoid);
             msg_list.extend(msgs);
-            *last_med_cadence_send = time_check;
+            reporting.last_med_cadence_send = time_check;
         }
 
         //Slow cadence: 1Hz approx
-        if (0 ==  *last_slow_cadence_send) ||
-            (state_r.elapsed_since(*last_slow_cadence_send) > SLOW_CADENCE_MICROSECONDS) {
-            let msgs = collect_slow_cadence_sensors(&state_r);
+        if (0 ==  reporting.last_slow_cadence_send) ||
+            (state_r.elapsed_since(reporting.last_slow_cadence_send) > SLOW_CADENCE_MICROSECONDS) {
+            let gps_fix = reporting.gps.sample(time_check);
+            let msgs = collect_slow_cadence_sensors(&state_r, &reporting.battery, &gps_fix, &reporting.gps_clock, &reporting.geoid);
             msg_list.extend(msgs);
-            *last_slow_cadence_send = time_check;
+            reporting.last_slow_cadence_send = time_check;
         }
     }
     msg_list
@@ -84,35 +111,32 @@ pub fn collect_messages(sim: &Arc<RwLock<Simulato>>,
 /// - Baro rate should be 100 Hz
 /// - Airspeed should be 100 Hz
 ///
-pub fn reporting_loop(sim:Arc<RwLock<Simulato>>, conn:Arc<Box<UorbConnection+Send+Sync>>) {
-    {
-        //send a first message to establish a time base (abs time offset)
-        let state_r = sim.read().unwrap();
-        let (hdr, msg) = gen_wrapped_timesync_status(&state_r);
-        let first_msg = vec![(hdr, msg)];
-        let res = send_all_messages(&**conn, first_msg);
-        if res.is_err() {
-            println!("first send failed: {:?}", res);
-            return;
+/// Messages go out through `router`, whose first route is the link to the mav firmware,
+/// so its filters decide which of the generated topics are sent, and where.
+pub fn reporting_loop(sim:Arc<RwLock<Simulato>>,
+                      router: Router,
+                      mut reporting: ReportingState) {
+    //send a first message to establish a time base (abs time offset)
+    loop {
+        match send_timesync(&sim, &router) {
+            Ok(()) => break,
+            // eg a udpin server that has not heard from its peer yet
+            Err(ref e) if e.kind() == ErrorKind::NotConnected => thread::sleep(PEER_WAIT_INTERVAL),
+            Err(e) => {
+                println!("first send failed: {:?}", e);
+                return;
+            }
         }
     }
 
-    let mut last_slow_cadence_send:TimeBaseUnits = 0; //1Hz sensors
-    let mut last_med_cadence_send:TimeBaseUnits = 0; //100Hz sensors
-    let mut last_fast_cadence_send:TimeBaseUnits = 0; //400Hz sensors
-
     loop {
         thread::sleep(Duration::from_micros(100));
         //thread::yield_now();
-        let msg_list = collect_messages(&sim,
-            &mut last_slow_cadence_send,
-            &mut last_med_cadence_send,
-            &mut last_fast_cadence_send
-        );
+        let msg_list = collect_messages(&sim, &mut reporting);
         if msg_list.len() > 0 {
             //send all messages
             //let start = SystemTime::now();
-            let res = send_all_messages(&**conn, msg_list);
+            let res = send_all_messages(&router, msg_list);
             if res.is_err() {
                 println!("sending failed: {:?}", res);
                 return;
@@ -124,109 +148,141 @@ pub fn reporting_loop(sim:Arc<RwLock<Simulato>>, conn:Arc<Box<UorbConnection+Sen
 }
 
 
+/// Send the timesync handshake that establishes the time base (abs time offset).
+///
+/// This must be the first message on every new link, including after a reconnect.
+pub fn send_timesync(sim: &Arc<RwLock<Simulato>>, conn: &dyn UorbConnection) -> Result<(), Error> {
+    let first_msg = {
+        let state_r = sim.read().unwrap();
+        vec![gen_wrapped_timesync_status(&state_r)]
+    };
+    send_all_messages(conn, first_msg)
+}
+
+/// Send every message collected in one tick as a single batch, so that
+/// stream transports issue one write per tick rather than one per message
 fn send_all_messages( conn: &UorbConnection,  msg_list: Vec<(UorbHeader, UorbMessage)>) -> Result<(), Error> {
-    for (hdr, msg) in msg_list {
-        conn.send(&hdr, &msg)?;
-    }
-    Ok(())
+    conn.send_batch(&msg_list)
 }
 
 
-fn gen_wrapped_vehicle_attitude(state: &Simulato)-> (UorbHeader, UorbMessage) {
-    let msg_data = gen_wrapped_vehicle_attitude_data(state);
+fn gen_wrapped_vehicle_attitude(state: &Simulato, attitude: &AttitudeTracker)-> (UorbHeader, UorbMessage) {
+    let msg_data = gen_wrapped_vehicle_attitude_data(state, attitude);
     msg_data.gen_ready_pair(0, state.get_simulated_time())
 }
 
-fn gen_wrapped_vehicle_attitude_data(state: &Simulato)-> VehicleAttitudeData {
+fn gen_wrapped_vehicle_attitude_data(state: &Simulato, attitude: &AttitudeTracker)-> VehicleAttitudeData {
     VehicleAttitudeData {
         timestamp: state.get_simulated_time(),
         rollspeed: state.vehicle_state.kinematic.body_angular_velocity[0],
         pitchspeed: state.vehicle_state.kinematic.body_angular_velocity[1],
         yawspeed: state.vehicle_state.kinematic.body_angular_velocity[2],
-        q: [0.0, 0.0, 0.0, 1.0], //TODO upright quaternion always
-        delta_q_reset: [0.0, 0.0, 0.0, 0.0],
-        quat_reset_counter: 0,
+        q: attitude.quaternion(),
+        delta_q_reset: attitude.delta_q_reset(),
+        quat_reset_counter: attitude.reset_counter(),
     }
 }
 
 
-fn gen_wrapped_battery_status(state: &Simulato) -> (UorbHeader, UorbMessage) {
-    let msg_data = gen_battery_status_data(state);
+fn gen_wrapped_battery_status(state: &Simulato, battery: &BatteryModel) -> (UorbHeader, UorbMessage) {
+    let msg_data = gen_battery_status_data(state, battery);
     msg_data.gen_ready_pair(0, state.get_simulated_time())
 }
 
-fn gen_battery_status_data(state: &Simulato) -> BatteryStatusData {
+/// Whole units, saturating at zero and at the largest reportable value
+fn saturating_u16(value: f32) -> u16 {
+    value.max(0.0).min(std::u16::MAX as f32) as u16
+}
+
+fn gen_battery_status_data(state: &Simulato, battery: &BatteryModel) -> BatteryStatusData {
+    let config = battery.config();
+    // only the first four cells are reported; a balanced pack has no spread between cells
+    let mut voltage_cell_v = [0.0; 4];
+    for cell_v in voltage_cell_v.iter_mut().take(config.cell_count as usize) {
+        *cell_v = battery.cell_voltage();
+    }
+
     BatteryStatusData {
         timestamp: state.get_simulated_time(),
-        voltage_v: 16.0,
-        voltage_filtered_v: 16.0,
-        current_a: 0.0,
-        current_filtered_a: 0.0,
-        average_current_a: 0.0,
-        discharged_mah: 0.0,
-        remaining: 10500.0,
+        voltage_v: battery.voltage(),
+        voltage_filtered_v: battery.voltage_filtered(),
+        current_a: battery.current(),
+        current_filtered_a: battery.current_filtered(),
+        average_current_a: battery.average_current(),
+        discharged_mah: battery.discharged_mah(),
+        remaining: battery.remaining(),
         scale: 1.0,
         temperature: state.vehicle_state.base_temperature,
-        cell_count: 4,
+        cell_count: config.cell_count as i32,
         connected: true,
         system_source: true,
         priority: 1,
-        capacity: 12000,
+        capacity: saturating_u16(config.capacity_mah),
         cycle_count: 5,
-        run_time_to_empty: 2350,
-        average_time_to_empty: 2350,
+        run_time_to_empty: saturating_u16(battery.run_time_to_empty()),
+        average_time_to_empty: saturating_u16(battery.average_time_to_empty()),
         serial_number: 12345,
-        voltage_cell_v: [3.2, 3.2, 3.2, 3.2],
-        max_cell_voltage_delta: 3.2,
+        voltage_cell_v,
+        max_cell_voltage_delta: 0.0,
         is_powering_off: false,
-        warning: BatteryStatusData::BATTERY_WARNING_NONE,
+        warning: battery.warning(),
     }
 }
 
-fn gen_wrapped_gps_position_msg(state: &Simulato) -> (UorbHeader, UorbMessage) {
-    let msg_data = gen_gps_msg_data(state);
+fn gen_wrapped_gps_position_msg(state: &Simulato, fix: &GpsFix, clock: &GpsClock, geoid: &Geoid) -> (UorbHeader, UorbMessage) {
+    let msg_data = gen_gps_msg_data(state, fix, clock, geoid);
     msg_data.gen_ready_pair(0, state.get_simulated_time())
 }
 
-fn gen_gps_msg_data(state: &Simulato) -> VehicleGpsPositionData {
-    //TODO ensure we use the same altitude that baro has already generated
+fn gen_gps_msg_data(state: &Simulato, fix: &GpsFix, clock: &GpsClock, geoid: &Geoid) -> VehicleGpsPositionData {
+    // the same position that the baro pressure is derived from
     let pos = state.sensed.gps.get_global_pos();
-    let vel = state.sensed.gps.get_velocity();
-    let alt_mm = (pos.alt_wgs84 * 1E3) as i32;
+    let true_vel = state.sensed.gps.get_velocity();
+    let (lat, lon) = offset_lat_lon(pos.lat, pos.lon, fix.position_error[0], fix.position_error[1]);
+    // the error is down, altitude is up
+    let alt_ellipsoid = pos.alt_wgs84 as f32 - fix.position_error[2];
+
+    let vel_n = true_vel[0] + fix.velocity_error[0];
+    let vel_e = true_vel[1] + fix.velocity_error[1];
+    let vel_d = true_vel[2] + fix.velocity_error[2];
+    let ground_speed = vel_n.hypot(vel_e);
+    let vel_ned_ground_valid = fix.has_fix() && ground_speed > GPS_HVEL_MINIMUM_VALID;
 
-    let vel_ned_ground_valid = vel[3] > GPS_HVEL_MINIMUM_VALID;
+    // without satellites there is no time solution either
+    let (time_utc_usec, timestamp_time_relative) =
+        if fix.has_fix() { clock.solution_time(state.get_simulated_time()) } else { (0, 0) };
 
     VehicleGpsPositionData {
         timestamp: state.get_simulated_time(),
-        time_utc_usec: 0,
+        time_utc_usec,
 
-        lat: (pos.lat * WHOLE_DEGREE_MULT) as i32,
-        lon: (pos.lon * WHOLE_DEGREE_MULT) as i32,
-        alt: alt_mm, //TODO not strictly accurate-- AMSL not the same as above-ellipsoid
-        alt_ellipsoid: alt_mm, //accurate
+        lat: (lat * WHOLE_DEGREE_MULT) as i32,
+        lon: (lon * WHOLE_DEGREE_MULT) as i32,
+        alt: (geoid.amsl_from_ellipsoid(alt_ellipsoid) * 1E3) as i32,
+        alt_ellipsoid: (alt_ellipsoid * 1E3) as i32,
 
-        s_variance_m_s: 0.0,
-        c_variance_rad: 0.0,
-        fix_type: 3, //3D
-        eph: 0.01,
-        epv: 0.01,
-        hdop: 0.0,
-        vdop: 0.0,
+        s_variance_m_s: fix.speed_variance(),
+        c_variance_rad: fix.course_variance(ground_speed),
+        fix_type: fix.fix_type,
+        eph: fix.eph,
+        epv: fix.epv,
+        hdop: fix.hdop,
+        vdop: fix.vdop,
 
         noise_per_ms: 0,
         jamming_indicator: 0,
 
-        vel_n_m_s: if vel_ned_ground_valid { vel[0] } else {0.0},
-        vel_e_m_s: if vel_ned_ground_valid { vel[1] } else {0.0},
-        vel_d_m_s: vel[2],
-        vel_m_s:   if vel_ned_ground_valid { vel[3] } else {0.0},
+        vel_n_m_s: if vel_ned_ground_valid { vel_n } else {0.0},
+        vel_e_m_s: if vel_ned_ground_valid { vel_e } else {0.0},
+        vel_d_m_s: if fix.has_fix() { vel_d } else {0.0},
+        vel_m_s:   if vel_ned_ground_valid { ground_speed } else {0.0},
         vel_ned_valid: vel_ned_ground_valid,
 
         //course over ground (cod) = atan2(y,x)
-        cog_rad: if vel_ned_ground_valid { vel[1].atan2(vel[0]) } else { 0.0  },
+        cog_rad: if vel_ned_ground_valid { vel_e.atan2(vel_n) } else { 0.0  },
 
-        timestamp_time_relative: 0,
-        satellites_used: 11,
+        timestamp_time_relative,
+        satellites_used: fix.satellites_used,
         heading: NAN,
         heading_offset: NAN,
     }
@@ -349,17 +405,19 @@ fn gen_sensor_mag_data(state: &Simulato, device_id: u32) -> SensorMagData {
 
 const SIM_BARO_DEVICE_ID: u32 = 478459;
 
-fn gen_wrapped_sensor_baro(state: &Simulato) -> (UorbHeader, UorbMessage) {
-    let msg_data = gen_sensor_baro_data(state, SIM_BARO_DEVICE_ID);
+fn gen_wrapped_sensor_baro(state: &Simulato, geoid: &Geoid) -> (UorbHeader, UorbMessage) {
+    let msg_data = gen_sensor_baro_data(state, SIM_BARO_DEVICE_ID, geoid);
     msg_data.gen_ready_pair(0, state.get_simulated_time())
 }
 
-fn gen_sensor_baro_data(state: &Simulato, device_id: u32) -> SensorBaroData {
+fn gen_sensor_baro_data(state: &Simulato, device_id: u32, geoid: &Geoid) -> SensorBaroData {
+    // the simulator treats the ellipsoid as sea level: move the pressure to the geoid datum the GPS reports
+    let alt_ellipsoid = state.sensed.gps.get_global_pos().alt_wgs84 as f32;
     SensorBaroData {
         timestamp: state.get_simulated_time(),
         device_id,
         error_count: 0,
-        pressure: state.sensed.baro.get_val() ,
+        pressure: state.sensed.baro.get_val() * geoid.pressure_scale(alt_ellipsoid),
         temperature: state.vehicle_state.base_temperature,
     }
 }
@@ -437,22 +495,29 @@ fn collect_fast_cadence_sensors(state: &Simulato) -> Vec<(UorbHeader, UorbMessag
 /// Mag rate should be 100 Hz
 /// Baro rate should be 100 Hz
 /// Airspeed should be 100 Hz
-fn collect_med_cadence_sensors(state: &Simulato) -> Vec<(UorbHeader, UorbMessage)> {
+fn collect_med_cadence_sensors(state: &Simulato,
+                               attitude: &AttitudeTracker,
+                               geoid: &Geoid
+) -> Vec<(UorbHeader, UorbMessage)> {
     let mut msg_list = vec![];
     msg_list.push( gen_wrapped_sensor_mag(state) );
-    msg_list.push( gen_wrapped_sensor_baro(state) );
+    msg_list.push( gen_wrapped_sensor_baro(state, geoid) );
     msg_list.push( gen_wrapped_differential_pressure(state) );
-    //TODO for now we force the attitude to upright
-    msg_list.push( gen_wrapped_vehicle_attitude(state) );
+    msg_list.push( gen_wrapped_vehicle_attitude(state, attitude) );
     msg_list
 }
 
 /// Gps rate should be about 1 Hz
 /// Battery status rate should be about 1 Hz
-fn collect_slow_cadence_sensors(state: &Simulato) -> Vec<(UorbHeader, UorbMessage)> {
+fn collect_slow_cadence_sensors(state: &Simulato,
+                                battery: &BatteryModel,
+                                gps_fix: &GpsFix,
+                                gps_clock: &GpsClock,
+                                geoid: &Geoid
+) -> Vec<(UorbHeader, UorbMessage)> {
     let mut msg_list = vec![];
-    msg_list.push(gen_wrapped_gps_position_msg(state));
-    msg_list.push(gen_wrapped_battery_status(state));
+    msg_list.push(gen_wrapped_gps_position_msg(state, gps_fix, gps_clock, geoid));
+    msg_list.push(gen_wrapped_battery_status(state, battery));
     msg_list
 }
 
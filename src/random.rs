@@ -0,0 +1,11 @@
+use std::f64::consts::PI;
+
+use rand::Rng;
+
+/// A standard normal variate, by the Box-Muller transform
+pub(crate) fn standard_normal<R: Rng + ?Sized>(rng: &mut R) -> f64 {
+    // u1 is kept away from zero so ln(u1) is finite
+    let u1: f64 = 1.0 - rng.gen::<f64>();
+    let u2: f64 = rng.gen::<f64>();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+}
@@ -0,0 +1,303 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use uorb_codec::{UorbHeader, UorbMessage};
+use uorb_codec::read_msg;
+use uorb_codec::write_msg;
+
+use crate::connection::UorbConnection;
+
+/// Identifies a session log file
+pub const LOG_FILE_MAGIC: &[u8; 8] = b"UORBLOG1";
+
+/// Which way a recorded frame crossed the connection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    /// Received from the mav firmware
+    Inbound,
+    /// Sent to the mav firmware
+    Outbound,
+}
+
+impl Direction {
+    fn to_tag(self) -> u8 {
+        match self {
+            Direction::Inbound => b'I',
+            Direction::Outbound => b'O',
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<Direction> {
+        match tag {
+            b'I' => Some(Direction::Inbound),
+            b'O' => Some(Direction::Outbound),
+            _ => None,
+        }
+    }
+}
+
+/// One frame read back from a session log
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    pub direction: Direction,
+    /// Wall-clock microseconds since the recording started
+    pub elapsed_micros: u64,
+    pub header: UorbHeader,
+    pub msg: UorbMessage,
+}
+
+/// Append one record to a session log.
+///
+/// Each record is a direction tag byte and a little-endian `u64` timestamp,
+/// followed by the frame exactly as `uorb_codec::write_msg` puts it on the wire.
+pub fn write_record<W: Write>(writer: &mut W,
+                              direction: Direction,
+                              elapsed_micros: u64,
+                              header: &UorbHeader,
+                              msg: &UorbMessage
+) -> io::Result<()> {
+    let mut prefix = [0u8; 9];
+    prefix[0] = direction.to_tag();
+    prefix[1..].copy_from_slice(&elapsed_micros.to_le_bytes());
+    writer.write_all(&prefix)?;
+    write_msg(writer, header, msg)
+}
+
+/// Read the next record from a session log, or `None` at a clean end of file
+pub fn read_record<R: Read>(reader: &mut R) -> io::Result<Option<LogRecord>> {
+    let mut tag = [0u8; 1];
+    if 0 == reader.read(&mut tag)? {
+        return Ok(None);
+    }
+    let direction = Direction::from_tag(tag[0]).ok_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Invalid direction tag in session log",
+    ))?;
+
+    let mut stamp = [0u8; 8];
+    reader.read_exact(&mut stamp)?;
+    let (header, msg) = read_msg(reader)?;
+
+    Ok(Some(LogRecord {
+        direction,
+        elapsed_micros: u64::from_le_bytes(stamp),
+        header,
+        msg,
+    }))
+}
+
+/// Reads back the records of a session log written by `RecordingConnection`
+pub struct LogReader<R: Read> {
+    inner: R,
+}
+
+impl LogReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        LogReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> LogReader<R> {
+    /// Wrap a reader positioned at the start of a session log
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut magic = [0u8; 8];
+        inner.read_exact(&mut magic)?;
+        if &magic != LOG_FILE_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a session log"));
+        }
+        Ok(LogReader { inner })
+    }
+
+    pub fn next_record(&mut self) -> io::Result<Option<LogRecord>> {
+        read_record(&mut self.inner)
+    }
+}
+
+impl<R: Read> Iterator for LogReader<R> {
+    type Item = io::Result<LogRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_record() {
+            Ok(Some(record)) => Some(Ok(record)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+
+/// Decorates a connection, capturing every frame it sends or receives to a session log.
+///
+/// A failure to write the log is reported once and ends the recording,
+/// but never interrupts traffic on the underlying connection.
+pub struct RecordingConnection<C: UorbConnection> {
+    inner: C,
+    log: Mutex<Option<Box<dyn Write + Send>>>,
+    start: Instant,
+}
+
+impl<C: UorbConnection> RecordingConnection<C> {
+    /// Record to a new log file at `path`, replacing any existing file
+    pub fn create<P: AsRef<Path>>(inner: C, path: P) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        RecordingConnection::new(inner, file)
+    }
+
+    /// Record to an arbitrary writer
+    pub fn new<W: Write + Send + 'static>(inner: C, mut log: W) -> io::Result<Self> {
+        log.write_all(LOG_FILE_MAGIC)?;
+        Ok(RecordingConnection {
+            inner,
+            log: Mutex::new(Some(Box::new(log))),
+            start: Instant::now(),
+        })
+    }
+
+    /// Push any buffered records through to the log
+    pub fn flush(&self) -> io::Result<()> {
+        match self.log.lock() {
+            Ok(mut log) => match *log {
+                Some(ref mut writer) => writer.flush(),
+                None => Ok(()),
+            },
+            Err(_e) => Ok(()),
+        }
+    }
+
+    fn record(&self, direction: Direction, header: &UorbHeader, msg: &UorbMessage) {
+        let elapsed = self.start.elapsed();
+        let elapsed_micros = elapsed.as_secs() * 1_000_000 + elapsed.subsec_micros() as u64;
+        if let Ok(mut log) = self.log.lock() {
+            let res = match *log {
+                Some(ref mut writer) => write_record(writer, direction, elapsed_micros, header, msg),
+                None => return,
+            };
+            if let Err(e) = res {
+                println!("session recording stopped: {:?}", e);
+                *log = None;
+            }
+        }
+    }
+}
+
+impl<C: UorbConnection> UorbConnection for RecordingConnection<C> {
+    fn recv(&self) -> io::Result<(UorbHeader, UorbMessage)> {
+        let (header, msg) = self.inner.recv()?;
+        self.record(Direction::Inbound, &header, &msg);
+        Ok((header, msg))
+    }
+
+    fn try_recv(&self) -> io::Result<Option<(UorbHeader, UorbMessage)>> {
+        let res = self.inner.try_recv()?;
+        if let Some((ref header, ref msg)) = res {
+            self.record(Direction::Inbound, header, msg);
+        }
+        Ok(res)
+    }
+
+    fn send(&self, header: &UorbHeader, data: &UorbMessage) -> io::Result<()> {
+        self.inner.send(header, data)?;
+        self.record(Direction::Outbound, header, data);
+        Ok(())
+    }
+
+    fn send_batch(&self, msgs: &[(UorbHeader, UorbMessage)]) -> io::Result<()> {
+        self.inner.send_batch(msgs)?;
+        for (header, data) in msgs {
+            self.record(Direction::Outbound, header, data);
+        }
+        Ok(())
+    }
+
+    fn skipped_bytes(&self) -> u64 {
+        self.inner.skipped_bytes()
+    }
+}
+
+
+/// How a `ReplayConnection` paces the messages it serves
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplayTiming {
+    /// Reproduce the gaps between messages as they were recorded
+    Original,
+    /// Serve each message as soon as it is asked for
+    AsFastAsPossible,
+}
+
+struct ReplayState {
+    log: LogReader<Box<dyn Read + Send>>,
+    /// When the first message was served, and its recorded timestamp
+    origin: Option<(Instant, u64)>,
+}
+
+/// Plays back the inbound side of a session log written by `RecordingConnection`.
+///
+/// `recv` serves the recorded inbound messages in order, then fails with
+/// `UnexpectedEof` once the log is exhausted. Sent messages are discarded.
+pub struct ReplayConnection {
+    state: Mutex<ReplayState>,
+    timing: ReplayTiming,
+}
+
+impl ReplayConnection {
+    pub fn open<P: AsRef<Path>>(path: P, timing: ReplayTiming) -> io::Result<Self> {
+        ReplayConnection::new(BufReader::new(File::open(path)?), timing)
+    }
+
+    /// Replay from a reader positioned at the start of a session log
+    pub fn new<R: Read + Send + 'static>(reader: R, timing: ReplayTiming) -> io::Result<Self> {
+        let reader: Box<dyn Read + Send> = Box::new(reader);
+        Ok(ReplayConnection {
+            state: Mutex::new(ReplayState {
+                log: LogReader::new(reader)?,
+                origin: None,
+            }),
+            timing,
+        })
+    }
+}
+
+impl UorbConnection for ReplayConnection {
+    fn recv(&self) -> io::Result<(UorbHeader, UorbMessage)> {
+        let mut state = self.state.lock().map_err(|_e| io::Error::new(
+            io::ErrorKind::Other,
+            "replay lock poisoned",
+        ))?;
+        loop {
+            let record = match state.log.next_record()? {
+                Some(record) => record,
+                None => return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "End of session log",
+                )),
+            };
+            if record.direction != Direction::Inbound {
+                continue;
+            }
+
+            if ReplayTiming::Original == self.timing {
+                match state.origin {
+                    Some((started, first_micros)) => {
+                        let offset = Duration::from_micros(record.elapsed_micros.saturating_sub(first_micros));
+                        let elapsed = started.elapsed();
+                        if offset > elapsed {
+                            thread::sleep(offset - elapsed);
+                        }
+                    },
+                    None => {
+                        state.origin = Some((Instant::now(), record.elapsed_micros));
+                    },
+                }
+            }
+            return Ok((record.header, record.msg));
+        }
+    }
+
+    fn send(&self, _header: &UorbHeader, _data: &UorbMessage) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use uorb_codec::{UorbHeader, UorbMessage};
+
+use crate::connection::{poisoned, UorbConnection};
+
+/// Names of the topics the simulator sends or receives, as returned by `topic_name`
+pub const TOPIC_NAMES: [&str; 13] = [
+    "ActuatorControls0",
+    "ActuatorControls1",
+    "ActuatorOutputs",
+    "BatteryStatus",
+    "DifferentialPressure",
+    "SensorAccel",
+    "SensorBaro",
+    "SensorGyro",
+    "SensorMag",
+    "TimesyncStatus",
+    "VehicleAttitude",
+    "VehicleGpsPosition",
+    "VehicleStatus",
+];
+
+/// Name given by `topic_name` to any other topic
+pub const UNKNOWN_TOPIC: &str = "Unknown";
+
+/// The topic name of a message: its `UorbMessage` variant name, eg `"SensorGyro"`
+pub fn topic_name(msg: &UorbMessage) -> &'static str {
+    match msg {
+        UorbMessage::ActuatorControls0(_) => "ActuatorControls0",
+        UorbMessage::ActuatorControls1(_) => "ActuatorControls1",
+        UorbMessage::ActuatorOutputs(_) => "ActuatorOutputs",
+        UorbMessage::BatteryStatus(_) => "BatteryStatus",
+        UorbMessage::DifferentialPressure(_) => "DifferentialPressure",
+        UorbMessage::SensorAccel(_) => "SensorAccel",
+        UorbMessage::SensorBaro(_) => "SensorBaro",
+        UorbMessage::SensorGyro(_) => "SensorGyro",
+        UorbMessage::SensorMag(_) => "SensorMag",
+        UorbMessage::TimesyncStatus(_) => "TimesyncStatus",
+        UorbMessage::VehicleAttitude(_) => "VehicleAttitude",
+        UorbMessage::VehicleGpsPosition(_) => "VehicleGpsPosition",
+        UorbMessage::VehicleStatus(_) => "VehicleStatus",
+        _ => UNKNOWN_TOPIC,
+    }
+}
+
+/// What a matching rule does with a message
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Allow,
+    Deny,
+}
+
+/// Matches messages by topic and instance id. A `None` field matches anything.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub action: Action,
+    /// `UorbMessage` variant name, eg `"DifferentialPressure"`
+    pub topic: Option<String>,
+    pub instance_id: Option<u32>,
+}
+
+impl Rule {
+    /// Let through every instance of `topic`
+    pub fn allow(topic: &str) -> Self {
+        Rule {
+            action: Action::Allow,
+            topic: Some(topic.to_string()),
+            instance_id: None,
+        }
+    }
+
+    /// Suppress every instance of `topic`
+    pub fn deny(topic: &str) -> Self {
+        Rule {
+            action: Action::Deny,
+            topic: Some(topic.to_string()),
+            instance_id: None,
+        }
+    }
+
+    /// Narrow the rule to a single instance
+    pub fn instance(mut self, instance_id: u32) -> Self {
+        self.instance_id = Some(instance_id);
+        self
+    }
+
+    fn matches(&self, topic: &str, instance_id: u32) -> bool {
+        self.topic.as_ref().map_or(true, |t| t == topic)
+            && self.instance_id.map_or(true, |i| i == instance_id)
+    }
+}
+
+/// An ordered list of rules: the first rule that matches a message decides its fate,
+/// and messages matching no rule get the default action.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopicFilter {
+    rules: Vec<Rule>,
+    default: Action,
+}
+
+impl TopicFilter {
+    /// Pass everything not explicitly denied
+    pub fn allow_all() -> Self {
+        TopicFilter {
+            rules: vec![],
+            default: Action::Allow,
+        }
+    }
+
+    /// Pass only what is explicitly allowed
+    pub fn deny_all() -> Self {
+        TopicFilter {
+            rules: vec![],
+            default: Action::Deny,
+        }
+    }
+
+    /// Append a rule, checked after those already added
+    pub fn rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    pub fn permits(&self, topic: &str, instance_id: u32) -> bool {
+        let action = self.rules.iter()
+            .find(|rule| rule.matches(topic, instance_id))
+            .map_or(self.default, |rule| rule.action);
+        Action::Allow == action
+    }
+}
+
+impl Default for TopicFilter {
+    fn default() -> Self {
+        TopicFilter::allow_all()
+    }
+}
+
+struct Route {
+    conn: Arc<dyn UorbConnection + Send + Sync>,
+    filter: TopicFilter,
+    /// Errors on a required route are returned to the caller; others are dropped
+    required: bool,
+    /// Verdicts keyed by message hash and instance id, so each topic is only named once
+    verdicts: Mutex<HashMap<(u64, u32), bool>>,
+}
+
+impl Route {
+    fn select(&self, msgs: &[(UorbHeader, UorbMessage)]) -> io::Result<Vec<(UorbHeader, UorbMessage)>> {
+        let mut verdicts = self.verdicts.lock().map_err(poisoned)?;
+        Ok(msgs.iter()
+            .filter(|(header, msg)| {
+                let instance_id = u32::from(header.instance_id);
+                let filter = &self.filter;
+                *verdicts.entry((u64::from(header.hash), instance_id))
+                    .or_insert_with(|| filter.permits(topic_name(msg), instance_id))
+            })
+            .cloned()
+            .collect())
+    }
+}
+
+/// Fans the messages generated by the simulator out to one or more connections,
+/// each with its own topic filter.
+///
+/// The first route added is the primary link to the mav firmware: `recv` reads from it.
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Router {
+            routes: vec![],
+        }
+    }
+
+    /// Add a route whose send failures are reported to the caller
+    pub fn add_route(&mut self, conn: Arc<dyn UorbConnection + Send + Sync>, filter: TopicFilter) {
+        self.push_route(conn, filter, true);
+    }
+
+    /// Add a route whose send failures are ignored, eg an extra monitoring sink
+    pub fn add_optional_route(&mut self, conn: Arc<dyn UorbConnection + Send + Sync>, filter: TopicFilter) {
+        self.push_route(conn, filter, false);
+    }
+
+    fn push_route(&mut self, conn: Arc<dyn UorbConnection + Send + Sync>, filter: TopicFilter, required: bool) {
+        self.routes.push(Route {
+            conn,
+            filter,
+            required,
+            verdicts: Mutex::new(HashMap::new()),
+        });
+    }
+
+    fn primary(&self) -> io::Result<&Route> {
+        self.routes.first().ok_or_else(|| io::Error::new(
+            io::ErrorKind::NotConnected,
+            "Router has no routes",
+        ))
+    }
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Router::new()
+    }
+}
+
+impl UorbConnection for Router {
+    fn recv(&self) -> io::Result<(UorbHeader, UorbMessage)> {
+        self.primary()?.conn.recv()
+    }
+
+    fn try_recv(&self) -> io::Result<Option<(UorbHeader, UorbMessage)>> {
+        self.primary()?.conn.try_recv()
+    }
+
+    fn send(&self, header: &UorbHeader, data: &UorbMessage) -> io::Result<()> {
+        self.send_batch(&[(header.clone(), data.clone())])
+    }
+
+    /// Every route receives the messages its filter permits, even if an earlier route failed
+    fn send_batch(&self, msgs: &[(UorbHeader, UorbMessage)]) -> io::Result<()> {
+        let mut first_err = None;
+        for route in self.routes.iter() {
+            let res = route.select(msgs).and_then(|selected| {
+                if selected.is_empty() {
+                    return Ok(());
+                }
+                route.conn.send_batch(&selected)
+            });
+            if let Err(e) = res {
+                if route.required && first_err.is_none() {
+                    first_err = Some(e);
+                }
+            }
+        }
+        first_err.map_or(Ok(()), Err)
+    }
+
+    fn skipped_bytes(&self) -> u64 {
+        self.primary().map_or(0, |route| route.conn.skipped_bytes())
+    }
+}
@@ -0,0 +1,25 @@
+use flighty::physical_types::TimeBaseUnits;
+
+/// Follows simulated time from one update to the next.
+///
+/// Simulated time running backwards means the simulator restarted.
+#[derive(Debug, Clone, Default)]
+pub struct SimClock {
+    last_time: Option<TimeBaseUnits>,
+}
+
+impl SimClock {
+    /// Advance to `time` (microseconds), returning the seconds elapsed since the last update
+    /// and whether the simulator restarted in between.
+    ///
+    /// No time elapses on the first update, nor on the first after a restart.
+    pub fn advance(&mut self, time: TimeBaseUnits) -> (f32, bool) {
+        let (last_time, restarted) = match self.last_time {
+            Some(last_time) if time < last_time => (time, true),
+            Some(last_time) => (last_time, false),
+            None => (time, false),
+        };
+        self.last_time = Some(time);
+        ((time - last_time) as f32 / 1E6, restarted)
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use uorb_codec::{UorbHeader, UorbMessage};
+use uorb_codec::write_msg;
+
+use crate::connection::{is_timeout, poisoned, UorbConnection};
+use crate::router::{topic_name, UNKNOWN_TOPIC};
+
+/// Traffic counters for one `UorbMessage` variant
+#[derive(Debug, Clone)]
+pub struct TopicStats {
+    pub count: u64,
+    /// Encoded frame bytes, headers included
+    pub bytes: u64,
+    pub first_seen: Instant,
+    pub last_seen: Instant,
+    /// The uORB timestamp of the most recent message
+    pub last_timestamp: u64,
+}
+
+impl TopicStats {
+    /// Average achieved message rate between the first and last message
+    pub fn rate_hz(&self) -> f32 {
+        let span = self.last_seen.duration_since(self.first_seen).as_secs_f32();
+        if self.count < 2 || span <= 0.0 {
+            return 0.0;
+        }
+        (self.count - 1) as f32 / span
+    }
+}
+
+/// Traffic counters for one direction of a connection
+#[derive(Debug, Clone, Default)]
+pub struct DirectionStats {
+    /// Per-topic counters, keyed by `UorbMessage` variant name
+    pub topics: BTreeMap<String, TopicStats>,
+    /// Calls that timed out without a message (`recv` only)
+    pub timeouts: u64,
+    /// Calls that failed for any other reason, eg frames that could not be decoded
+    pub errors: u64,
+    /// Bytes discarded by the connection while resynchronizing (`recv` only)
+    pub skipped_bytes: u64,
+}
+
+impl DirectionStats {
+    pub fn total_count(&self) -> u64 {
+        self.topics.values().map(|t| t.count).sum()
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.topics.values().map(|t| t.bytes).sum()
+    }
+}
+
+/// A point-in-time copy of a connection's traffic counters
+#[derive(Debug, Clone)]
+pub struct StatsSnapshot {
+    /// Time since counting started
+    pub elapsed: Duration,
+    /// Messages received from the mav firmware
+    pub inbound: DirectionStats,
+    /// Messages sent to the mav firmware
+    pub outbound: DirectionStats,
+}
+
+fn fmt_direction(f: &mut fmt::Formatter, label: &str, stats: &DirectionStats) -> fmt::Result {
+    writeln!(f, "{}: {} msgs, {} bytes, {} timeouts, {} errors, {} bytes skipped",
+             label, stats.total_count(), stats.total_bytes(), stats.timeouts, stats.errors,
+             stats.skipped_bytes)?;
+    for (name, topic) in stats.topics.iter() {
+        writeln!(f, "  {:<28} {:>9} msgs {:>11} bytes {:>8.1} Hz  last ts {}",
+                 name, topic.count, topic.bytes, topic.rate_hz(), topic.last_timestamp)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for StatsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "traffic over {:.1}s", self.elapsed.as_secs_f32())?;
+        fmt_direction(f, "outbound", &self.outbound)?;
+        fmt_direction(f, "inbound", &self.inbound)
+    }
+}
+
+/// Name and encoded size of a topic, worked out once per message hash
+struct TopicInfo {
+    name: String,
+    frame_len: usize,
+}
+
+struct StatsState {
+    started: Instant,
+    inbound: DirectionStats,
+    outbound: DirectionStats,
+    topic_info: HashMap<u64, TopicInfo>,
+    /// Skipped byte count of the inner connection when counting (re)started
+    skipped_base: u64,
+    /// Latest skipped byte count of the inner connection
+    skipped_total: u64,
+}
+
+impl StatsState {
+    fn count(&mut self, outbound: bool, header: &UorbHeader, msg: &UorbMessage) {
+        let info = self.topic_info.entry(u64::from(header.hash)).or_insert_with(|| {
+            let name = match topic_name(msg) {
+                // keep topics the simulator doesn't name apart, by hash
+                UNKNOWN_TOPIC => format!("{}({:#x})", UNKNOWN_TOPIC, header.hash),
+                name => name.to_string(),
+            };
+            let mut frame = Vec::new();
+            let frame_len = write_msg(&mut frame, header, msg).map(|_| frame.len()).unwrap_or(0);
+            TopicInfo { name, frame_len }
+        });
+
+        let direction = if outbound { &mut self.outbound } else { &mut self.inbound };
+        let now = Instant::now();
+        let topic = direction.topics.entry(info.name.clone()).or_insert_with(|| TopicStats {
+            count: 0,
+            bytes: 0,
+            first_seen: now,
+            last_seen: now,
+            last_timestamp: 0,
+        });
+        topic.count += 1;
+        topic.bytes += info.frame_len as u64;
+        topic.last_seen = now;
+        topic.last_timestamp = header.timestamp;
+    }
+
+    fn update_skipped(&mut self, skipped_total: u64) {
+        self.skipped_total = skipped_total;
+        self.inbound.skipped_bytes = skipped_total.saturating_sub(self.skipped_base);
+    }
+}
+
+/// A cloneable handle for reading the counters of a `StatsConnection`,
+/// eg from a reporting thread after the connection has been shared.
+#[derive(Clone)]
+pub struct StatsHandle {
+    state: Arc<Mutex<StatsState>>,
+}
+
+impl StatsHandle {
+    pub fn snapshot(&self) -> StatsSnapshot {
+        // counters are only ever incremented, so they are still worth reading after a panic
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        StatsSnapshot {
+            elapsed: state.started.elapsed(),
+            inbound: state.inbound.clone(),
+            outbound: state.outbound.clone(),
+        }
+    }
+
+    /// Clear all counters and restart the clock
+    pub fn reset(&self) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.started = Instant::now();
+        state.inbound = DirectionStats::default();
+        state.outbound = DirectionStats::default();
+        state.skipped_base = state.skipped_total;
+    }
+}
+
+/// Decorates a connection, counting the traffic in each direction
+pub struct StatsConnection<C: UorbConnection> {
+    inner: C,
+    handle: StatsHandle,
+}
+
+impl<C: UorbConnection> StatsConnection<C> {
+    pub fn new(inner: C) -> Self {
+        StatsConnection {
+            inner,
+            handle: StatsHandle {
+                state: Arc::new(Mutex::new(StatsState {
+                    started: Instant::now(),
+                    inbound: DirectionStats::default(),
+                    outbound: DirectionStats::default(),
+                    topic_info: HashMap::new(),
+                    skipped_base: 0,
+                    skipped_total: 0,
+                })),
+            },
+        }
+    }
+
+    pub fn handle(&self) -> StatsHandle {
+        self.handle.clone()
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        self.handle.snapshot()
+    }
+}
+
+impl<C: UorbConnection> UorbConnection for StatsConnection<C> {
+    fn recv(&self) -> io::Result<(UorbHeader, UorbMessage)> {
+        let res = self.inner.recv();
+        let mut state = self.handle.state.lock().map_err(poisoned)?;
+        state.update_skipped(self.inner.skipped_bytes());
+        match res {
+            Ok((ref header, ref msg)) => state.count(false, header, msg),
+            Err(ref e) if is_timeout(e) => state.inbound.timeouts += 1,
+            Err(_) => state.inbound.errors += 1,
+        }
+        res
+    }
+
+    fn try_recv(&self) -> io::Result<Option<(UorbHeader, UorbMessage)>> {
+        let res = self.inner.try_recv();
+        let mut state = self.handle.state.lock().map_err(poisoned)?;
+        state.update_skipped(self.inner.skipped_bytes());
+        match res {
+            Ok(Some((ref header, ref msg))) => state.count(false, header, msg),
+            Ok(None) => state.inbound.timeouts += 1,
+            Err(_) => state.inbound.errors += 1,
+        }
+        res
+    }
+
+    fn send(&self, header: &UorbHeader, data: &UorbMessage) -> io::Result<()> {
+        let res = self.inner.send(header, data);
+        let mut state = self.handle.state.lock().map_err(poisoned)?;
+        match res {
+            Ok(()) => state.count(true, header, data),
+            Err(_) => state.outbound.errors += 1,
+        }
+        res
+    }
+
+    fn send_batch(&self, msgs: &[(UorbHeader, UorbMessage)]) -> io::Result<()> {
+        let res = self.inner.send_batch(msgs);
+        let mut state = self.handle.state.lock().map_err(poisoned)?;
+        match res {
+            Ok(()) => {
+                for (header, data) in msgs {
+                    state.count(true, header, data);
+                }
+            },
+            Err(_) => state.outbound.errors += 1,
+        }
+        res
+    }
+
+    fn skipped_bytes(&self) -> u64 {
+        self.inner.skipped_bytes()
+    }
+}
@@ -0,0 +1,81 @@
+extern crate mavulator;
+
+
+#[cfg(test)]
+mod test_airframe {
+    use mavulator::airframe::{Airframe, AirframeError, OutputMapping, PRESET_NAMES};
+
+    fn pwm_outputs(vals: &[f32]) -> [f32; 16] {
+        let mut pwm = [std::f32::NAN; 16];
+        pwm[..vals.len()].copy_from_slice(vals);
+        pwm
+    }
+
+    /// quad-x should map exactly as the fixed four-rotor mapping did
+    #[test]
+    pub fn test_quad_x_matches_fixed_mapping() {
+        let pwm = pwm_outputs(&[1000.0, 1500.0, 2000.0, 900.0, 1000.0, 1500.0, 2000.0, 0.0]);
+        let ctrls = Airframe::quad_x().map_outputs(&pwm);
+        assert_eq!(&ctrls[..8], &[0.0, 0.5, 1.0, 0.0, -1.0, 0.0, 1.0, 0.0]);
+        assert_eq!(&ctrls[8..], &[0.0; 8]);
+    }
+
+    /// Multirotor presets should treat the right number of channels as rotors
+    #[test]
+    pub fn test_multirotor_presets() {
+        let pwm = pwm_outputs(&[1000.0; 10]);
+        for (airframe, nrotors) in vec![(Airframe::hex_x(), 6), (Airframe::octo_x(), 8)] {
+            let ctrls = airframe.map_outputs(&pwm);
+            assert!(ctrls[..nrotors].iter().all(|c| *c == 0.0), "{} rotors", airframe.name);
+            assert!(ctrls[nrotors..10].iter().all(|c| *c == -1.0), "{} surfaces", airframe.name);
+        }
+    }
+
+    /// The standard plane drives three surfaces and one throttle, and ignores other outputs
+    #[test]
+    pub fn test_standard_plane() {
+        let pwm = pwm_outputs(&[2000.0, 1000.0, 1500.0, 1250.0, 2000.0]);
+        let ctrls = Airframe::standard_plane().map_outputs(&pwm);
+        assert_eq!(&ctrls[..5], &[1.0, -1.0, 0.0, 0.25, 0.0]);
+    }
+
+    /// Outputs can be routed to any control
+    #[test]
+    pub fn test_reordered_mapping() {
+        let airframe = Airframe::new("custom", vec![
+            OutputMapping::rotor(0, 2),
+            OutputMapping::rotor(2, 0),
+            OutputMapping::surface(5, 1),
+        ]).unwrap();
+        let pwm = pwm_outputs(&[1250.0, 2000.0, 1750.0, 2000.0, 2000.0, 1000.0]);
+        let ctrls = airframe.map_outputs(&pwm);
+        assert_eq!(&ctrls[..4], &[0.75, -1.0, 0.25, 0.0]);
+    }
+
+    /// Reversible rotors map symmetrically about center, with a deadband around it
+    #[test]
+    pub fn test_reversible_rotors() {
+        let airframe = Airframe::quad_x().with_reversible_rotors(50.0);
+        let pwm = pwm_outputs(&[1000.0, 1525.0, 1775.0, 1225.0, 1250.0]);
+        let ctrls = airframe.map_outputs(&pwm);
+        assert_eq!(&ctrls[..5], &[-1.0, 0.0, 0.5, -0.5, -0.5]);
+
+        // without a deadband, thrust follows the PWM straight through center
+        let ctrls = Airframe::quad_x().with_reversible_rotors(0.0).map_outputs(&pwm);
+        assert_eq!(&ctrls[..2], &[-1.0, 0.05]);
+    }
+
+    #[test]
+    pub fn test_airframe_errors() {
+        assert_eq!(Airframe::new("bad", vec![OutputMapping::rotor(16, 0)]),
+                   Err(AirframeError::InvalidChannel(16)));
+        assert_eq!(Airframe::new("bad", vec![OutputMapping::rotor(0, 0), OutputMapping::rotor(1, 0)]),
+                   Err(AirframeError::DuplicateControl(0)));
+        assert_eq!(Airframe::preset("tricopter"),
+                   Err(AirframeError::UnknownPreset("tricopter".to_string())));
+        for name in PRESET_NAMES.iter() {
+            assert_eq!(&Airframe::preset(name).unwrap().name, name);
+        }
+    }
+
+}
@@ -0,0 +1,95 @@
+extern crate mavulator;
+
+
+#[cfg(test)]
+mod test_attitude {
+    use std::f32::consts::FRAC_PI_2;
+    use mavulator::attitude::{quaternion_multiply, AttitudeTracker, Quaternion, IDENTITY_QUATERNION};
+
+    const STEP_MICROS: u64 = 2500;
+
+    fn assert_quaternion_near(actual: &Quaternion, expected: &Quaternion) {
+        // q and -q are the same rotation
+        let sign = if actual.iter().zip(expected.iter()).map(|(a, e)| a * e).sum::<f32>() < 0.0 { -1.0 } else { 1.0 };
+        for (a, e) in actual.iter().zip(expected.iter()) {
+            assert!((sign * a - e).abs() < 1e-3, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    /// Rotate at constant body rates for `secs`, starting at `start` microseconds,
+    /// feeding the tracker the orientation a simulator integrating those rates would have
+    fn rotate(tracker: &mut AttitudeTracker, start: u64, secs: f32, rates: [f32; 3]) -> u64 {
+        let steps = (secs * 1E6) as u64 / STEP_MICROS;
+        let dt = STEP_MICROS as f32 / 1E6;
+        let mut time = start;
+        let mut q = tracker.quaternion();
+        tracker.update(time, q);
+        for _i in 0..steps {
+            time += STEP_MICROS;
+            let half = [0.0, rates[0] * dt / 2.0, rates[1] * dt / 2.0, rates[2] * dt / 2.0];
+            let dq = quaternion_multiply(&q, &half);
+            q = [q[0] + dq[0], q[1] + dq[1], q[2] + dq[2], q[3] + dq[3]];
+            tracker.update(time, q);
+        }
+        time
+    }
+
+    /// A quarter turn about one axis, as a quaternion
+    fn quarter_turn(axis: usize) -> Quaternion {
+        let mut q = [(FRAC_PI_2 / 2.0).cos(), 0.0, 0.0, 0.0];
+        q[axis + 1] = (FRAC_PI_2 / 2.0).sin();
+        q
+    }
+
+    /// The published attitude should track the integrated yaw rate
+    #[test]
+    pub fn test_constant_yaw_rate() {
+        let mut tracker = AttitudeTracker::default();
+        rotate(&mut tracker, 1_000_000, 1.0, [0.0, 0.0, FRAC_PI_2]);
+        assert_quaternion_near(&tracker.quaternion(), &quarter_turn(2));
+        assert_eq!(tracker.reset_counter(), 0);
+    }
+
+    /// Body rates compose in the body frame: roll then pitch is not pitch then roll
+    #[test]
+    pub fn test_body_frame_composition() {
+        let mut tracker = AttitudeTracker::default();
+        let time = rotate(&mut tracker, 1_000_000, 1.0, [FRAC_PI_2, 0.0, 0.0]);
+        // hold still for a moment
+        let time = rotate(&mut tracker, time, 0.1, [0.0, 0.0, 0.0]);
+        rotate(&mut tracker, time, 1.0, [0.0, FRAC_PI_2, 0.0]);
+
+        let expected = quaternion_multiply(&quarter_turn(0), &quarter_turn(1));
+        assert_quaternion_near(&tracker.quaternion(), &expected);
+
+        let norm: f32 = tracker.quaternion().iter().map(|v| v * v).sum();
+        assert!((norm - 1.0).abs() < 1e-5);
+    }
+
+    /// Before any reset, the reset delta is the identity rotation rather than zero
+    #[test]
+    pub fn test_initial_reset_delta() {
+        let tracker = AttitudeTracker::default();
+        assert_eq!(tracker.delta_q_reset(), IDENTITY_QUATERNION);
+        assert_eq!(tracker.reset_counter(), 0);
+    }
+
+    /// A simulator restart is recorded as a reset, and the reset delta maps the old attitude to the new
+    #[test]
+    pub fn test_reset_on_time_reversal() {
+        let mut tracker = AttitudeTracker::default();
+        let time = rotate(&mut tracker, 1_000_000, 1.0, [0.0, 0.0, FRAC_PI_2]);
+        let before = tracker.quaternion();
+
+        tracker.update(time - 500_000, IDENTITY_QUATERNION);
+        assert_eq!(tracker.reset_counter(), 1);
+        assert_quaternion_near(&tracker.quaternion(), &IDENTITY_QUATERNION);
+        assert_quaternion_near(&quaternion_multiply(&tracker.delta_q_reset(), &before), &tracker.quaternion());
+
+        // tracking carries on from the new time base
+        rotate(&mut tracker, time - 500_000, 1.0, [0.0, 0.0, FRAC_PI_2]);
+        assert_quaternion_near(&tracker.quaternion(), &quarter_turn(2));
+        assert_eq!(tracker.reset_counter(), 1);
+    }
+
+}
@@ -0,0 +1,165 @@
+extern crate mavulator;
+
+
+#[cfg(test)]
+mod test_battery {
+    use mavulator::airframe::Airframe;
+    use mavulator::battery::{cell_open_circuit_voltage, BatteryConfig, BatteryModel};
+    use mavulator::mav_reader::InputMode;
+    use uorb_codec::common::BatteryStatusData;
+
+    const STEP_MICROS: u64 = 10_000;
+
+    /// A battery without avionics load, so that current is entirely down to the rotors
+    fn rotor_only_config() -> BatteryConfig {
+        BatteryConfig {
+            idle_current: 0.0,
+            ..BatteryConfig::default()
+        }
+    }
+
+    /// Hold the same controls for `secs`, starting at `start` microseconds
+    fn run(battery: &mut BatteryModel, start: u64, secs: f32, controls: &[f32; 16]) -> u64 {
+        let steps = (secs * 1E6) as u64 / STEP_MICROS;
+        let mut time = start;
+        battery.update(time, controls);
+        for _i in 0..steps {
+            time += STEP_MICROS;
+            battery.update(time, controls);
+        }
+        time
+    }
+
+    fn thrust(level: f32) -> [f32; 16] {
+        let mut controls = [0.0; 16];
+        controls[..4].copy_from_slice(&[level; 4]);
+        controls
+    }
+
+    /// Open circuit voltage falls with charge, from a full 4.2V per cell
+    #[test]
+    pub fn test_discharge_curve() {
+        assert!((cell_open_circuit_voltage(1.0) - 4.2).abs() < 1e-5);
+        assert!((cell_open_circuit_voltage(2.0) - 4.2).abs() < 1e-5);
+        let mut last = cell_open_circuit_voltage(0.0);
+        for pct in 1..=100 {
+            let volts = cell_open_circuit_voltage(pct as f32 / 100.0);
+            assert!(volts > last, "{} at {}%", volts, pct);
+            last = volts;
+        }
+    }
+
+    /// Discharged charge is the integral of the current drawn
+    #[test]
+    pub fn test_discharge_integrates_current() {
+        let config = rotor_only_config();
+        let mut battery = BatteryModel::new(config.clone(), vec![0, 1, 2, 3]);
+        run(&mut battery, 1_000_000, 60.0, &thrust(1.0));
+
+        // a full minute at full thrust
+        let expected_mah = config.max_load_current * 1000.0 / 60.0;
+        assert!((battery.current() - config.max_load_current).abs() < 1e-3);
+        assert!((battery.discharged_mah() - expected_mah).abs() < 1.0, "{} mAh", battery.discharged_mah());
+        assert!((battery.remaining() - (1.0 - expected_mah / config.capacity_mah)).abs() < 1e-3);
+    }
+
+    /// The pack voltage sags under load, and recovers when the load is removed
+    #[test]
+    pub fn test_voltage_sag() {
+        let config = rotor_only_config();
+        let mut battery = BatteryModel::new(config.clone(), vec![0, 1, 2, 3]);
+        let time = run(&mut battery, 1_000_000, 1.0, &thrust(0.0));
+        let rested = battery.voltage();
+        assert!((rested - 4.2 * config.cell_count as f32).abs() < 0.01, "{} V", rested);
+
+        let time = run(&mut battery, time, 1.0, &thrust(1.0));
+        let sag = rested - battery.voltage();
+        let expected_sag = config.max_load_current * config.cell_resistance * config.cell_count as f32;
+        assert!(sag > expected_sag, "sag {} V", sag);
+        // the filtered voltage lags behind
+        assert!(battery.voltage_filtered() > battery.voltage());
+
+        run(&mut battery, time, 1.0, &thrust(0.0));
+        assert!(battery.voltage() > rested - expected_sag);
+        assert!((battery.cell_voltage() * config.cell_count as f32 - battery.voltage()).abs() < 1e-3);
+    }
+
+    /// Only the rotors load the battery: surfaces don't, and reversed thrust does
+    #[test]
+    pub fn test_load_controls() {
+        let plane = Airframe::standard_plane();
+        assert_eq!(plane.rotor_controls(), vec![3]);
+        assert_eq!(Airframe::hex_x().rotor_controls(), vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(InputMode::ActuatorControls.load_controls(), vec![3]);
+
+        let mut battery = BatteryModel::new(rotor_only_config(), plane.rotor_controls());
+        battery.update(1_000_000, &[1.0, -1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        assert_eq!(battery.current(), 0.0);
+
+        let mut battery = BatteryModel::new(rotor_only_config(), vec![0, 1, 2, 3]);
+        battery.update(1_000_000, &thrust(0.5));
+        let forward = battery.current();
+        battery.update(1_010_000, &thrust(-0.5));
+        assert!(forward > 0.0);
+        assert!((battery.current() - forward).abs() < 1e-6);
+    }
+
+    /// Warnings escalate as the battery runs down, and stay raised
+    #[test]
+    pub fn test_warnings() {
+        let config = BatteryConfig {
+            capacity_mah: 1000.0,
+            ..rotor_only_config()
+        };
+        let mut battery = BatteryModel::new(config, vec![0, 1, 2, 3]);
+        let mut time = 1_000_000;
+        let mut warnings = vec![battery.warning()];
+        while battery.remaining() > 0.0 {
+            time = run(&mut battery, time, 1.0, &thrust(1.0));
+            if battery.warning() != *warnings.last().unwrap() {
+                warnings.push(battery.warning());
+            }
+        }
+        assert_eq!(warnings, vec![
+            BatteryStatusData::BATTERY_WARNING_NONE,
+            BatteryStatusData::BATTERY_WARNING_LOW,
+            BatteryStatusData::BATTERY_WARNING_CRITICAL,
+            BatteryStatusData::BATTERY_WARNING_EMERGENCY,
+        ]);
+        assert_eq!(battery.run_time_to_empty(), 0.0);
+
+        // resting doesn't clear the warning
+        run(&mut battery, time, 1.0, &thrust(0.0));
+        assert_eq!(battery.warning(), BatteryStatusData::BATTERY_WARNING_EMERGENCY);
+    }
+
+    /// Time to empty follows the present and the average current
+    #[test]
+    pub fn test_time_to_empty() {
+        let config = rotor_only_config();
+        let mut battery = BatteryModel::new(config.clone(), vec![0, 1, 2, 3]);
+        let time = run(&mut battery, 1_000_000, 1.0, &thrust(1.0));
+        let remaining_mah = config.capacity_mah - battery.discharged_mah();
+        let expected_minutes = remaining_mah / (config.max_load_current * 1000.0) * 60.0;
+        assert!((battery.run_time_to_empty() - expected_minutes).abs() < 0.01);
+        // the average hasn't caught up with the load yet
+        assert!(battery.average_time_to_empty() > battery.run_time_to_empty());
+
+        run(&mut battery, time, 1.0, &thrust(0.0));
+        assert!(battery.run_time_to_empty().is_infinite());
+    }
+
+    /// A simulator restart starts over with a full battery
+    #[test]
+    pub fn test_recharge_on_time_reversal() {
+        let mut battery = BatteryModel::new(BatteryConfig::default(), vec![0, 1, 2, 3]);
+        let time = run(&mut battery, 1_000_000, 10.0, &thrust(1.0));
+        assert!(battery.discharged_mah() > 0.0);
+
+        battery.update(time - 5_000_000, &thrust(0.0));
+        assert_eq!(battery.discharged_mah(), 0.0);
+        assert_eq!(battery.remaining(), 1.0);
+        assert_eq!(battery.warning(), BatteryStatusData::BATTERY_WARNING_NONE);
+    }
+
+}
@@ -0,0 +1,153 @@
+extern crate mavulator;
+
+
+#[cfg(test)]
+mod test_calibration {
+    use proptest::prelude::*;
+    use mavulator::airframe::Airframe;
+    use mavulator::calibration::{CalibrationError, PwmCalibration, PwmChannel};
+
+    const TOLERANCE: f32 = 1e-4;
+
+    prop_compose! {
+        fn arb_channel()(min in 800.0f32..1200.0,
+                         max in 1800.0f32..2200.0,
+                         trim_frac in 0.2f32..0.8,
+                         reversed in any::<bool>()) -> PwmChannel {
+            PwmChannel {
+                min,
+                max,
+                trim: min + trim_frac * (max - min),
+                disarmed: 0.0,
+                reversed,
+            }
+        }
+    }
+
+    proptest! {
+        /// Outputs stay within their ranges, whatever the input
+        #[test]
+        fn prop_outputs_bounded(channel in arb_channel(), pwm in 0.0f32..3000.0) {
+            let thrust = channel.unipolar(pwm);
+            let deflection = channel.bipolar(pwm);
+            prop_assert!((0.0..=1.0).contains(&thrust), "thrust {}", thrust);
+            prop_assert!((-1.0..=1.0).contains(&deflection), "deflection {}", deflection);
+        }
+
+        /// Endpoints and trim land exactly on the ends and middle of the range
+        #[test]
+        fn prop_endpoints(channel in arb_channel()) {
+            let (low, high) = if channel.reversed { (channel.max, channel.min) } else { (channel.min, channel.max) };
+            prop_assert!(channel.unipolar(low).abs() < TOLERANCE);
+            prop_assert!((channel.unipolar(high) - 1.0).abs() < TOLERANCE);
+            prop_assert!((channel.bipolar(low) + 1.0).abs() < TOLERANCE);
+            prop_assert!((channel.bipolar(high) - 1.0).abs() < TOLERANCE);
+            if !channel.reversed {
+                prop_assert!(channel.bipolar(channel.trim).abs() < TOLERANCE);
+            }
+        }
+
+        /// Increasing PWM never moves the output backwards, or forwards when reversed
+        #[test]
+        fn prop_monotonic(channel in arb_channel(), a in 700.0f32..2300.0, b in 700.0f32..2300.0) {
+            let (lo, hi) = if a <= b { (a, b) } else { (b, a) };
+            if channel.reversed {
+                prop_assert!(channel.unipolar(lo) >= channel.unipolar(hi));
+                prop_assert!(channel.bipolar(lo) >= channel.bipolar(hi));
+            }
+            else {
+                prop_assert!(channel.unipolar(lo) <= channel.unipolar(hi));
+                prop_assert!(channel.bipolar(lo) <= channel.bipolar(hi));
+            }
+        }
+
+        /// Reversal mirrors the thrust mapping about the middle of the range
+        #[test]
+        fn prop_reversal_mirrors(channel in arb_channel(), pwm in 800.0f32..2200.0) {
+            let forward = PwmChannel { reversed: false, ..channel };
+            let reverse = PwmChannel { reversed: true, ..channel };
+            let sum = forward.unipolar(pwm) + reverse.unipolar(pwm);
+            prop_assert!((sum - 1.0).abs() < TOLERANCE, "sum {}", sum);
+        }
+
+        /// Reversible thrust is bounded, zero inside the deadband, and symmetric about a centered trim
+        #[test]
+        fn prop_deadband(channel in arb_channel(), offset in 0.0f32..600.0, deadband in 0.0f32..100.0) {
+            let centered = PwmChannel { trim: (channel.min + channel.max) / 2.0, reversed: false, ..channel };
+            let forward = centered.bipolar_with_deadband(centered.trim + offset, deadband);
+            let backward = centered.bipolar_with_deadband(centered.trim - offset, deadband);
+            prop_assert!((-1.0..=1.0).contains(&forward), "forward {}", forward);
+            prop_assert!((forward + backward).abs() < TOLERANCE, "{} vs {}", forward, backward);
+            if offset <= deadband {
+                prop_assert!(forward == 0.0);
+            }
+            else {
+                prop_assert!(forward > 0.0);
+            }
+            prop_assert!((centered.bipolar_with_deadband(centered.max, deadband) - 1.0).abs() < TOLERANCE);
+        }
+    }
+
+    /// The output matching the disarmed value, and NaN outputs, map to zero
+    #[test]
+    pub fn test_disarmed_outputs() {
+        let channel = PwmChannel::default();
+        assert!(!channel.is_active(channel.disarmed));
+        assert!(!channel.is_active(std::f32::NAN));
+        assert!(!channel.is_active(0.0));
+        assert!(channel.is_active(channel.min));
+    }
+
+    /// Per-channel parameters override the global ones, in either file format
+    #[test]
+    pub fn test_from_px4_params() {
+        let params = "\
+# Onboard parameters for Vehicle 1
+1\t1\tPWM_MAIN_MIN\t1100\t6
+1\t1\tPWM_MAIN_MAX\t1900\t6
+1\t1\tPWM_MAIN_DISARM\t950\t6
+1\t1\tPWM_MAIN_REV\t2\t6
+1\t1\tMC_ROLL_P\t6.5\t9
+PWM_MAIN_MIN3 1200
+PWM_MAIN_MAX3 -1
+PWM_MAIN_DIS3 1000
+PWM_MAIN_TRIM4 0.5
+PWM_MAIN_REV5 1
+";
+        let cal = PwmCalibration::from_params(params).unwrap();
+        assert_eq!(cal.channels[0], PwmChannel {
+            min: 1100.0, max: 1900.0, trim: 1500.0, disarmed: 950.0, reversed: false,
+        });
+        assert!(cal.channels[1].reversed);
+        assert_eq!((cal.channels[2].min, cal.channels[2].max, cal.channels[2].disarmed), (1200.0, 1900.0, 1000.0));
+        assert_eq!(cal.channels[3].trim, 1700.0);
+        assert!(cal.channels[4].reversed);
+        assert!(!cal.channels[5].reversed);
+    }
+
+    #[test]
+    pub fn test_invalid_params() {
+        match PwmCalibration::from_params("PWM_MAIN_MIN1 low") {
+            Err(CalibrationError::InvalidParam(_)) => {},
+            other => panic!("unexpected result {:?}", other),
+        }
+        match PwmCalibration::from_params("PWM_MAIN_MIN2 2100") {
+            Err(CalibrationError::InvalidChannel(1)) => {},
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    /// An airframe applies each output's own endpoints
+    #[test]
+    pub fn test_calibrated_airframe() {
+        let cal = PwmCalibration::from_params("PWM_MAIN_MIN1 1100\nPWM_MAIN_MAX1 1900\nPWM_MAIN_REV5 1").unwrap();
+        let airframe = Airframe::quad_x().with_calibration(cal);
+        let mut pwm = [std::f32::NAN; 16];
+        pwm[0] = 1500.0;
+        pwm[1] = 1500.0;
+        pwm[4] = 1250.0;
+        let ctrls = airframe.map_outputs(&pwm);
+        assert_eq!(&ctrls[..5], &[0.5, 0.5, 0.0, 0.0, 0.5]);
+    }
+
+}
@@ -0,0 +1,70 @@
+extern crate mavulator;
+
+mod test_shared;
+
+
+#[cfg(test)]
+mod test_channel_pair {
+    use std::io;
+    use std::thread;
+    use crate::test_shared;
+    use mavulator::connection::{self, UorbConnection};
+
+    /// Messages sent on either endpoint should arrive, in order, at the other
+    #[test]
+    pub fn test_pair_both_directions() {
+        let (sim_end, firmware_end) = connection::pair(None);
+
+        for i in 0..5 {
+            let (header, msg) = test_shared::get_vehicle_status_msg(17, i);
+            sim_end.send(&header, &msg).unwrap();
+        }
+        let (header, msg) = test_shared::get_vehicle_status_msg(17, 42);
+        firmware_end.send(&header, &msg).unwrap();
+
+        for i in 0..5 {
+            assert_eq!(firmware_end.recv().unwrap().0.timestamp, i);
+        }
+        assert_eq!(sim_end.recv().unwrap().0.timestamp, 42);
+
+        // nothing pending: recv times out rather than blocking forever
+        match sim_end.recv() {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {},
+            other => panic!("unexpected result {:?}", other.map(|(h, _m)| h.timestamp)),
+        }
+    }
+
+    /// A bounded pair should apply backpressure without losing messages
+    #[test]
+    pub fn test_pair_bounded_capacity() {
+        const MSG_COUNT: u64 = 50;
+        let (sim_end, firmware_end) = connection::pair(Some(2));
+
+        let sender = thread::spawn(move || {
+            for i in 0..MSG_COUNT {
+                let (header, msg) = test_shared::get_vehicle_status_msg(17, i);
+                sim_end.send(&header, &msg).unwrap();
+            }
+        });
+
+        for i in 0..MSG_COUNT {
+            assert_eq!(firmware_end.recv().unwrap().0.timestamp, i);
+        }
+        sender.join().unwrap();
+    }
+
+    /// Dropping one endpoint should be reported to the other
+    #[test]
+    pub fn test_pair_peer_dropped() {
+        let (sim_end, firmware_end) = connection::pair(None);
+        drop(firmware_end);
+
+        let (header, msg) = test_shared::get_vehicle_status_msg(17, 0);
+        assert!(sim_end.send(&header, &msg).is_err());
+        match sim_end.recv() {
+            Err(ref e) if e.kind() == io::ErrorKind::NotConnected => {},
+            other => panic!("unexpected result {:?}", other.map(|(h, _m)| h.timestamp)),
+        }
+    }
+
+}
@@ -0,0 +1,49 @@
+extern crate mavulator;
+
+
+#[cfg(test)]
+mod test_geoid {
+    use mavulator::geoid::{standard_pressure, Geoid};
+
+    /// Pressure altitude in the standard atmosphere: the inverse of `standard_pressure`
+    fn pressure_altitude(pressure: f32) -> f32 {
+        (1.0 - (pressure / 101_325.0).powf(1.0 / 5.255_88)) * 288.15 / 0.0065
+    }
+
+    #[test]
+    pub fn test_standard_atmosphere() {
+        assert!((standard_pressure(0.0) - 101_325.0).abs() < 0.01);
+        assert!((standard_pressure(1000.0) - 89_875.0).abs() < 5.0, "{}", standard_pressure(1000.0));
+        assert!((standard_pressure(5000.0) - 54_020.0).abs() < 20.0, "{}", standard_pressure(5000.0));
+    }
+
+    /// AMSL and ellipsoid altitude differ by the undulation, in either direction
+    #[test]
+    pub fn test_altitude_datums() {
+        let geoid = Geoid::new(-32.0);
+        assert_eq!(geoid.amsl_from_ellipsoid(10.0), 42.0);
+        assert_eq!(geoid.ellipsoid_from_amsl(42.0), 10.0);
+
+        let sea_level = Geoid::default();
+        assert_eq!(sea_level.amsl_from_ellipsoid(10.0), 10.0);
+        assert_eq!(sea_level.pressure_scale(10.0), 1.0);
+    }
+
+    /// Baro pressure, once scaled, gives the same AMSL altitude that the GPS reports
+    #[test]
+    pub fn test_baro_uses_amsl() {
+        for undulation in [-32.0f32, 0.0, 45.0].iter() {
+            let geoid = Geoid::new(*undulation);
+            for alt_ellipsoid in [-20.0f32, 10.0, 500.0, 3000.0].iter() {
+                let pressure = standard_pressure(*alt_ellipsoid) * geoid.pressure_scale(*alt_ellipsoid);
+                let baro_alt = pressure_altitude(pressure);
+                let gps_alt = geoid.amsl_from_ellipsoid(*alt_ellipsoid);
+                assert!((baro_alt - gps_alt).abs() < 0.05, "baro {} gps {}", baro_alt, gps_alt);
+            }
+        }
+
+        // the geoid below the ellipsoid puts the vehicle higher above sea level, in thinner air
+        assert!(Geoid::new(-32.0).pressure_scale(10.0) < 1.0);
+    }
+
+}
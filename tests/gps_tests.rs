@@ -0,0 +1,175 @@
+extern crate mavulator;
+
+
+#[cfg(test)]
+mod test_gps {
+    use std::time::Duration;
+    use mavulator::gps::*;
+
+    /// GPS reports are sent at 5Hz
+    const STEP_MICROS: u64 = 200_000;
+    const START_MICROS: u64 = 1_000_000;
+
+    fn run(model: &mut GpsErrorModel, secs: u64) -> Vec<GpsFix> {
+        (0..secs * 1_000_000 / STEP_MICROS)
+            .map(|i| model.sample(START_MICROS + i * STEP_MICROS))
+            .collect()
+    }
+
+    fn rms<I: Iterator<Item=f32>>(vals: I) -> f32 {
+        let (sum, count) = vals.fold((0.0f64, 0), |(sum, count), val| (sum + (val * val) as f64, count + 1));
+        (sum / count as f64).sqrt() as f32
+    }
+
+    fn assert_near(actual: f32, expected: f32, tolerance: f32) {
+        assert!((actual - expected).abs() <= tolerance * expected, "{} != {}", actual, expected);
+    }
+
+    /// A short drift time constant, so that a few thousand samples are nearly independent
+    fn steady_config() -> GpsErrorConfig {
+        GpsErrorConfig {
+            drift_time_constant: 1.0,
+            min_satellites: 11,
+            max_satellites: 11,
+            ..GpsErrorConfig::typical()
+        }
+    }
+
+    /// The default receiver reports a steady, accurate 3D fix
+    #[test]
+    pub fn test_ideal_receiver() {
+        let mut model = GpsErrorModel::default();
+        for fix in run(&mut model, 60) {
+            assert_eq!(fix.fix_type, GPS_FIX_TYPE_3D);
+            assert_eq!(fix.satellites_used, 11);
+            assert!(fix.eph < 0.01 && fix.epv < 0.02, "{:?}", fix);
+            assert!(fix.position_error.iter().all(|err| err.abs() < 0.1), "{:?}", fix);
+            assert_eq!(fix.velocity_error, [0.0; 3]);
+        }
+    }
+
+    /// The injected errors have the spread that eph, epv and the speed accuracy claim
+    #[test]
+    pub fn test_accuracy_matches_error() {
+        let mut model = GpsErrorModel::new(steady_config());
+        let fixes = run(&mut model, 4000);
+        let eph = fixes[0].eph;
+        let epv = fixes[0].epv;
+        assert!(fixes.iter().all(|fix| fix.eph == eph && fix.epv == epv));
+
+        let horizontal = rms(fixes.iter().map(|fix| fix.position_error[0].hypot(fix.position_error[1])));
+        let vertical = rms(fixes.iter().map(|fix| fix.position_error[2]));
+        let velocity = rms(fixes.iter().map(|fix| fix.velocity_error[0]));
+        assert_near(horizontal, eph, 0.1);
+        assert_near(vertical, epv, 0.1);
+        assert_near(velocity, fixes[0].speed_accuracy, 0.1);
+    }
+
+    /// The reported variances are the squares of the spread of the injected errors
+    #[test]
+    pub fn test_variance_matches_error() {
+        let mut model = GpsErrorModel::new(steady_config());
+        let fixes = run(&mut model, 4000);
+        let speed_variance = fixes[0].speed_variance();
+        assert_near(speed_variance, fixes[0].speed_accuracy * fixes[0].speed_accuracy, 1e-6);
+
+        let mean_square = rms(fixes.iter().map(|fix| fix.velocity_error[1])).powi(2);
+        assert_near(mean_square, speed_variance, 0.1);
+
+        let course_accuracy = fixes[0].course_accuracy(5.0);
+        assert_near(fixes[0].course_variance(5.0), course_accuracy * course_accuracy, 1e-6);
+        assert_eq!(fixes[0].course_variance(0.0), std::f32::consts::PI * std::f32::consts::PI);
+    }
+
+    /// The drift is correlated from one report to the next
+    #[test]
+    pub fn test_drift_is_correlated() {
+        let mut model = GpsErrorModel::new(GpsErrorConfig::typical());
+        let fixes = run(&mut model, 60);
+        let step = rms(fixes.windows(2).map(|pair| pair[1].position_error[0] - pair[0].position_error[0]));
+        let spread = fixes[0].eph / std::f32::consts::SQRT_2;
+        assert!(step < spread / 5.0, "step {} spread {}", step, spread);
+    }
+
+    /// The satellite count wanders within its bounds, and DOP and accuracy follow it
+    #[test]
+    pub fn test_satellites_vary() {
+        let config = GpsErrorConfig::typical();
+        let mut model = GpsErrorModel::new(config.clone());
+        let fixes = run(&mut model, 3600);
+        let min = fixes.iter().map(|fix| fix.satellites_used).min().unwrap();
+        let max = fixes.iter().map(|fix| fix.satellites_used).max().unwrap();
+        assert!(min >= config.min_satellites && max <= config.max_satellites);
+        assert!(max > min);
+
+        for fix in fixes.iter() {
+            assert_eq!(fix.hdop, hdop_for_satellites(fix.satellites_used));
+            assert!(fix.vdop > fix.hdop);
+            assert_near(fix.eph, config.range_error * fix.hdop, 1e-5);
+        }
+        assert!(hdop_for_satellites(6) > hdop_for_satellites(12));
+    }
+
+    /// No fix during a scheduled outage, then a fix with a larger error that settles
+    #[test]
+    pub fn test_scheduled_outage() {
+        let config = GpsErrorConfig {
+            outages: vec![FixOutage { start: Duration::from_secs(10), duration: Duration::from_secs(5) }],
+            ..steady_config()
+        };
+        let mut model = GpsErrorModel::new(config);
+        let fixes = run(&mut model, 30);
+        let at = |secs: f32| fixes[(secs * 5.0) as usize];
+
+        let settled = at(9.8).eph;
+        assert!(at(9.8).has_fix());
+        for fix in fixes[50..75].iter() {
+            assert_eq!(fix.fix_type, GPS_FIX_TYPE_NONE);
+            assert_eq!(fix.satellites_used, 0);
+            assert!(!fix.has_fix());
+        }
+        assert_eq!(at(15.0).fix_type, GPS_FIX_TYPE_3D);
+        assert!(at(15.0).eph > 2.5 * settled, "{} vs {}", at(15.0).eph, settled);
+        assert!(at(16.0).eph < at(15.0).eph);
+        assert_near(at(29.8).eph, settled, 0.01);
+    }
+
+    /// A simulator restart starts the outage schedule over
+    #[test]
+    pub fn test_schedule_restarts() {
+        let config = GpsErrorConfig {
+            outages: vec![FixOutage { start: Duration::from_secs(10), duration: Duration::from_secs(5) }],
+            ..GpsErrorConfig::default()
+        };
+        let mut model = GpsErrorModel::new(config);
+        assert!(model.sample(1_000_000).has_fix());
+        assert!(!model.sample(12_000_000).has_fix());
+        assert!(model.sample(20_000_000).has_fix());
+
+        // restarted at 2s: the outage is due at 12s again
+        assert!(model.sample(2_000_000).has_fix());
+        assert!(!model.sample(13_000_000).has_fix());
+    }
+
+    /// The same seed gives the same errors
+    #[test]
+    pub fn test_seed_reproducible() {
+        let fixes = run(&mut GpsErrorModel::new(GpsErrorConfig::typical()), 60);
+        assert_eq!(fixes, run(&mut GpsErrorModel::new(GpsErrorConfig::typical()), 60));
+
+        let reseeded = GpsErrorConfig { seed: 1, ..GpsErrorConfig::typical() };
+        assert_ne!(fixes, run(&mut GpsErrorModel::new(reseeded), 60));
+    }
+
+    #[test]
+    pub fn test_offset_lat_lon() {
+        let (lat, lon) = offset_lat_lon(0.0, 10.0, 1000.0, 1000.0);
+        assert!((lat - 0.008993).abs() < 1e-5, "{}", lat);
+        assert!((lon - 10.008993).abs() < 1e-5, "{}", lon);
+
+        // a meter east is twice as many degrees at 60 degrees latitude
+        let (_lat, lon) = offset_lat_lon(60.0, 10.0, 0.0, 1000.0);
+        assert!((lon - 10.017986).abs() < 1e-5, "{}", lon);
+    }
+
+}
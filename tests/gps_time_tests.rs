@@ -0,0 +1,103 @@
+extern crate mavulator;
+
+
+#[cfg(test)]
+mod test_gps_time {
+    use proptest::prelude::*;
+    use mavulator::gps_time::{GpsClock, GpsTimeError, SimulationEpoch, UtcDateTime};
+
+    fn date_time(s: &str) -> UtcDateTime {
+        s.parse().unwrap()
+    }
+
+    proptest! {
+        /// Converting to unix time and back gives the same date and time, to the microsecond
+        #[test]
+        fn prop_unix_round_trip(unix_micros in 0u64..4_102_444_800_000_000) {
+            let utc = UtcDateTime::from_unix_micros(unix_micros);
+            prop_assert_eq!(utc.unix_micros(), unix_micros);
+            prop_assert!(UtcDateTime::new(utc.year, utc.month, utc.day, utc.hour, utc.minute, utc.second).is_some());
+            let reparsed = date_time(&utc.to_string());
+            prop_assert_eq!(reparsed.unix_micros(), unix_micros - unix_micros % 1_000_000);
+        }
+
+        /// Consecutive days are 86400 seconds apart, with no leap seconds
+        #[test]
+        fn prop_days_are_uniform(day in 0u64..47_482) {
+            let midnight = UtcDateTime::from_unix_micros(day * 86_400_000_000);
+            prop_assert_eq!((midnight.hour, midnight.minute, midnight.second), (0, 0, 0));
+            let before = UtcDateTime::from_unix_micros(day * 86_400_000_000 + 86_399_999_999);
+            prop_assert_eq!((before.year, before.month, before.day), (midnight.year, midnight.month, midnight.day));
+            prop_assert_eq!((before.hour, before.minute, before.second), (23, 59, 59));
+        }
+    }
+
+    /// Known dates, either side of leap days and of a leap second that unix time ignores
+    #[test]
+    pub fn test_known_dates() {
+        assert_eq!(date_time("1970-01-01T00:00:00Z").unix_micros(), 0);
+        assert_eq!(date_time("2000-02-29T00:00:00Z").unix_micros(), 951_782_400_000_000);
+        assert_eq!(date_time("2000-03-01T00:00:00Z").unix_micros(), 951_868_800_000_000);
+        // 2016-12-31T23:59:60Z was a leap second: unix time goes straight on to 2017
+        assert_eq!(date_time("2016-12-31T23:59:59Z").unix_micros(), 1_483_228_799_000_000);
+        assert_eq!(date_time("2017-01-01T00:00:00Z").unix_micros(), 1_483_228_800_000_000);
+        assert_eq!(date_time("2038-01-19T03:14:08Z").unix_micros(), (1u64 << 31) * 1_000_000);
+        assert_eq!(UtcDateTime::from_unix_micros(1_583_020_799_500_000).to_string(), "2020-02-29T23:59:59Z");
+    }
+
+    #[test]
+    pub fn test_invalid_date_times() {
+        for s in ["2019-02-29T00:00:00Z", "2100-02-29T00:00:00Z", "2020-13-01T00:00:00Z",
+                  "2016-12-31T23:59:60Z", "1969-12-31T23:59:59Z", "2020-01-01 00:00:00Z",
+                  "2020-01-01T00:00:00", "2020-1-01T00:00:00Z", "2020-01-01T+0:00:00Z"].iter() {
+            assert_eq!(s.parse::<UtcDateTime>(), Err(GpsTimeError::InvalidDateTime(s.to_string())));
+        }
+    }
+
+    #[test]
+    pub fn test_parse_epoch() {
+        assert_eq!("now".parse::<SimulationEpoch>(), Ok(SimulationEpoch::Now));
+        assert_eq!("2020-06-01T12:00:00Z".parse::<SimulationEpoch>(), Ok(SimulationEpoch::Fixed(date_time("2020-06-01T12:00:00Z"))));
+        assert_eq!("1591012800".parse::<SimulationEpoch>(), Ok(SimulationEpoch::Fixed(date_time("2020-06-01T12:00:00Z"))));
+        assert_eq!("yesterday".parse::<SimulationEpoch>(), Err(GpsTimeError::InvalidEpoch("yesterday".to_string())));
+        assert!("".parse::<SimulationEpoch>().is_err());
+
+        // the last second of the year 9999 is the latest epoch, rather than wrapping the year
+        assert_eq!("253402300799".parse::<SimulationEpoch>(), Ok(SimulationEpoch::Fixed(date_time("9999-12-31T23:59:59Z"))));
+        assert_eq!("253402300800".parse::<SimulationEpoch>(), Err(GpsTimeError::InvalidEpoch("253402300800".to_string())));
+        assert!("99999999999999999999".parse::<SimulationEpoch>().is_err());
+    }
+
+    /// UTC follows simulated time from the epoch, with each solution on the 5Hz UTC grid
+    #[test]
+    pub fn test_fixed_epoch_clock() {
+        let epoch = date_time("2020-06-01T12:00:00Z");
+        let clock = GpsClock::new(SimulationEpoch::Fixed(epoch));
+        assert_eq!(clock.epoch_micros(), epoch.unix_micros());
+        assert_eq!(clock.utc_micros(0), epoch.unix_micros());
+        assert_eq!(UtcDateTime::from_unix_micros(clock.utc_micros(90_061_000_000)).to_string(), "2020-06-02T13:01:01Z");
+
+        assert_eq!(clock.solution_time(1_000_000), (epoch.unix_micros() + 1_000_000, 0));
+        let (utc, relative) = clock.solution_time(1_250_000);
+        assert_eq!(utc, epoch.unix_micros() + 1_200_000);
+        assert_eq!(relative, -50_000);
+
+        let clock = clock.with_measurement_period(1_000_000);
+        assert_eq!(clock.solution_time(1_250_000), (epoch.unix_micros() + 1_000_000, -250_000));
+        // an epoch between solutions: the first solution is at the epoch, not before it
+        let epoch = UtcDateTime { micros: 500_000, ..epoch };
+        let clock = GpsClock::new(SimulationEpoch::Fixed(epoch)).with_measurement_period(1_000_000);
+        assert_eq!(clock.solution_time(100_000), (epoch.unix_micros(), -100_000));
+        assert_eq!(clock.solution_time(600_000), (epoch.unix_micros() + 500_000, -100_000));
+    }
+
+    /// An epoch of now reads the wall clock once, when the clock is made
+    #[test]
+    pub fn test_now_epoch() {
+        let before = SimulationEpoch::Fixed(date_time("2020-01-01T00:00:00Z")).unix_micros();
+        let clock = GpsClock::new(SimulationEpoch::Now);
+        assert!(clock.epoch_micros() > before);
+        assert_eq!(clock.utc_micros(1_000_000), clock.epoch_micros() + 1_000_000);
+    }
+
+}
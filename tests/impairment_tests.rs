@@ -0,0 +1,143 @@
+extern crate mavulator;
+
+mod test_shared;
+
+
+#[cfg(test)]
+mod test_impairment {
+    use std::time::Duration;
+    use crate::test_shared;
+    use mavulator::connection::{self, UorbConnection};
+    use mavulator::impairment::{DelayDistribution, ImpairedConnection, ImpairmentConfig, ImpairmentProfile, LinkModel};
+
+    /// Send `count` messages through an impaired link and collect the timestamps that arrive
+    fn run_link(send_profile: ImpairmentProfile, seed: u64, count: u64) -> Vec<u64> {
+        let (sim_end, firmware_end) = connection::pair(None);
+        let impaired = ImpairedConnection::new(sim_end, ImpairmentConfig {
+            send: send_profile,
+            seed,
+            ..Default::default()
+        });
+
+        for i in 0..count {
+            let (header, msg) = test_shared::get_vehicle_status_msg(17, i);
+            impaired.send(&header, &msg).unwrap();
+        }
+
+        let mut received = vec![];
+        while let Ok((header, _msg)) = firmware_end.recv() {
+            received.push(header.timestamp);
+        }
+        received
+    }
+
+    #[test]
+    pub fn test_unimpaired_link_is_transparent() {
+        let received = run_link(ImpairmentProfile::default(), 1, 20);
+        assert_eq!(received, (0..20).collect::<Vec<u64>>());
+    }
+
+    /// Send `count` messages through a link model, one per millisecond of simulated time,
+    /// and collect the timestamps in the order they are delivered
+    fn run_model(profile: ImpairmentProfile, seed: u64, count: u64) -> Vec<u64> {
+        let mut model = LinkModel::new(profile, seed);
+        for i in 0..count {
+            let (header, msg) = test_shared::get_vehicle_status_msg(17, i);
+            model.send(Duration::from_millis(i), &header, &msg);
+        }
+        model.deliver(Duration::from_secs(3600)).iter()
+            .map(|(header, _msg)| header.timestamp)
+            .collect()
+    }
+
+    #[test]
+    pub fn test_drop_and_duplicate() {
+        let dropped = run_model(ImpairmentProfile {
+            drop_probability: 1.0,
+            ..Default::default()
+        }, 1, 20);
+        assert!(dropped.is_empty());
+
+        let duplicated = run_model(ImpairmentProfile {
+            duplicate_probability: 1.0,
+            ..Default::default()
+        }, 1, 5);
+        assert_eq!(duplicated, vec![0, 0, 1, 1, 2, 2, 3, 3, 4, 4]);
+    }
+
+    #[test]
+    pub fn test_same_seed_same_losses() {
+        let profile = ImpairmentProfile {
+            drop_probability: 0.5,
+            ..Default::default()
+        };
+        let first = run_model(profile.clone(), 1234, 50);
+        let second = run_model(profile.clone(), 1234, 50);
+        assert_eq!(first, second);
+        assert!(first.len() > 0 && first.len() < 50);
+    }
+
+    #[test]
+    pub fn test_reordering() {
+        let profile = ImpairmentProfile {
+            reorder_probability: 0.5,
+            reorder_hold: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let received = run_model(profile.clone(), 99, 20);
+        let mut sorted = received.clone();
+        sorted.sort();
+        assert_eq!(sorted, (0..20).collect::<Vec<u64>>());
+        assert_ne!(received, sorted);
+        assert_eq!(received, run_model(profile, 99, 20));
+    }
+
+    /// Jitter delays messages, but never lets one overtake another
+    #[test]
+    pub fn test_jitter_keeps_order() {
+        let received = run_model(ImpairmentProfile {
+            delay: DelayDistribution::Uniform { min: Duration::from_millis(0), max: Duration::from_millis(30) },
+            ..Default::default()
+        }, 7, 50);
+        assert_eq!(received, (0..50).collect::<Vec<u64>>());
+    }
+
+    /// Messages are held for exactly the configured latency
+    #[test]
+    pub fn test_constant_latency() {
+        let mut model = LinkModel::new(ImpairmentProfile {
+            delay: DelayDistribution::Constant(Duration::from_millis(50)),
+            ..Default::default()
+        }, 1);
+        let (header, msg) = test_shared::get_vehicle_status_msg(17, 7);
+        model.send(Duration::from_millis(10), &header, &msg);
+
+        assert_eq!(model.next_due(), Some(Duration::from_millis(60)));
+        assert!(model.deliver(Duration::from_millis(59)).is_empty());
+        let delivered = model.deliver(Duration::from_millis(60));
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(delivered[0].0.timestamp, 7);
+        assert_eq!(model.next_due(), None);
+    }
+
+    #[test]
+    pub fn test_delay_applies_to_recv() {
+        let (sim_end, firmware_end) = connection::pair(None);
+        let impaired = ImpairedConnection::new(sim_end, ImpairmentConfig {
+            recv: ImpairmentProfile {
+                delay: DelayDistribution::Constant(Duration::from_millis(50)),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+
+        let (header, msg) = test_shared::get_vehicle_status_msg(17, 7);
+        firmware_end.send(&header, &msg).unwrap();
+        let mut res = impaired.recv();
+        while res.is_err() {
+            res = impaired.recv();
+        }
+        assert_eq!(res.unwrap().0.timestamp, 7);
+    }
+
+}
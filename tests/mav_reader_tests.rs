@@ -0,0 +1,43 @@
+extern crate mavulator;
+
+
+#[cfg(test)]
+mod test_mav_reader {
+    use mavulator::airframe::{Airframe, AirframeError};
+    use mavulator::mav_reader::{merge_control_group, InputMode};
+
+    /// Each group fills its own slot, keeping the latest value of the other group
+    #[test]
+    pub fn test_merge_control_groups() {
+        let mut ctrls = [0.0f32; 16];
+        merge_control_group(&mut ctrls, 0, &[0.1, -0.2, 0.3, 0.75, 0.0, 0.0, 0.0, 1.0]);
+        merge_control_group(&mut ctrls, 1, &[0.5, std::f32::NAN, 0.0, 0.0, 0.0, 0.0, 0.0, -1.0]);
+        assert_eq!(&ctrls[..8], &[0.1, -0.2, 0.3, 0.75, 0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(&ctrls[8..], &[0.5, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, -1.0]);
+
+        merge_control_group(&mut ctrls, 0, &[0.0; 8]);
+        assert_eq!(&ctrls[..8], &[0.0; 8]);
+        assert_eq!(ctrls[8], 0.5);
+
+        // groups beyond the flighty controls are ignored
+        merge_control_group(&mut ctrls, 2, &[1.0; 8]);
+        assert_eq!(ctrls.iter().filter(|c| **c == 1.0).count(), 0);
+    }
+
+    /// Mixer outputs stay the default input
+    #[test]
+    pub fn test_default_input_mode() {
+        assert_eq!(InputMode::default(), InputMode::MixerOutputs(Airframe::quad_x()));
+    }
+
+    /// Unmixed attitude controls can drive a plane, but not the rotors of a multirotor
+    #[test]
+    pub fn test_actuator_controls_need_unmixed_airframe() {
+        assert_eq!(InputMode::actuator_controls_for(&Airframe::standard_plane()), Ok(InputMode::ActuatorControls));
+        for airframe in [Airframe::quad_x(), Airframe::hex_x(), Airframe::octo_x()].iter() {
+            assert_eq!(InputMode::actuator_controls_for(airframe),
+                       Err(AirframeError::NeedsMixer(airframe.name.clone())));
+        }
+    }
+
+}
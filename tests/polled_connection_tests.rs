@@ -0,0 +1,86 @@
+extern crate mavulator;
+
+mod test_shared;
+
+
+#[cfg(test)]
+mod test_polled_connection {
+    use std::io;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use crate::test_shared;
+    use mavulator::connection::{self, PolledConnection, UorbConnection};
+
+    /// try_recv on a channel pair should report an empty queue without waiting
+    #[test]
+    pub fn test_channel_try_recv() {
+        let (sim_end, firmware_end) = connection::pair(None);
+
+        let started = Instant::now();
+        assert!(firmware_end.try_recv().unwrap().is_none());
+        assert!(started.elapsed() < Duration::from_millis(50));
+
+        let (header, msg) = test_shared::get_vehicle_status_msg(17, 7);
+        sim_end.send(&header, &msg).unwrap();
+        let (header, _msg) = firmware_end.try_recv().unwrap().unwrap();
+        assert_eq!(header.timestamp, 7);
+
+        drop(sim_end);
+        match firmware_end.try_recv() {
+            Err(ref e) if e.kind() == io::ErrorKind::NotConnected => {},
+            other => panic!("unexpected result {:?}", other.map(|m| m.map(|(h, _m)| h.timestamp))),
+        }
+    }
+
+    /// A polled connection should never block in try_recv, and should deliver in order
+    #[test]
+    pub fn test_polled_try_recv() {
+        let (sim_end, firmware_end) = connection::pair(None);
+        let polled = PolledConnection::new(sim_end);
+
+        let started = Instant::now();
+        assert!(polled.try_recv().unwrap().is_none());
+        assert!(started.elapsed() < Duration::from_millis(50));
+
+        for i in 0..5 {
+            let (header, msg) = test_shared::get_vehicle_status_msg(17, i);
+            firmware_end.send(&header, &msg).unwrap();
+        }
+
+        let mut received = vec![];
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while received.len() < 5 && Instant::now() < deadline {
+            match polled.try_recv().unwrap() {
+                Some((header, _msg)) => received.push(header.timestamp),
+                None => thread::yield_now(),
+            }
+        }
+        assert_eq!(received, vec![0, 1, 2, 3, 4]);
+
+        // sends pass straight through to the wrapped connection
+        let (header, msg) = test_shared::get_vehicle_status_msg(17, 42);
+        polled.send(&header, &msg).unwrap();
+        assert_eq!(firmware_end.recv().unwrap().0.timestamp, 42);
+    }
+
+    /// A blocked recv should wake as soon as a message arrives, not at the next poll
+    #[test]
+    pub fn test_polled_recv_wakes_on_arrival() {
+        let (sim_end, firmware_end) = connection::pair(None);
+        let polled = PolledConnection::new(sim_end);
+
+        let sender = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(30));
+            let (header, msg) = test_shared::get_vehicle_status_msg(17, 99);
+            firmware_end.send(&header, &msg).unwrap();
+            firmware_end
+        });
+
+        let started = Instant::now();
+        let (header, _msg) = polled.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(header.timestamp, 99);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        sender.join().unwrap();
+    }
+
+}
@@ -0,0 +1,102 @@
+extern crate mavulator;
+
+mod test_shared;
+
+
+#[cfg(test)]
+mod test_router {
+    use std::sync::Arc;
+    use crate::test_shared;
+    use mavulator::connection::{self, UorbConnection};
+    use mavulator::router::{Router, Rule, TopicFilter};
+    use uorb_codec::common::*;
+    use uorb_codec::{UorbHeader, UorbMsgMeta};
+
+    fn get_diff_pressure_msg(timestamp: u64) -> (UorbHeader, UorbMessage) {
+        let msg_data = DifferentialPressureData {
+            timestamp,
+            device_id: 0,
+            error_count: 0,
+            differential_pressure_raw_pa: 1.5,
+            differential_pressure_filtered_pa: 1.5,
+            temperature: 20.0,
+        };
+        msg_data.gen_ready_pair(0, timestamp)
+    }
+
+    /// Drain everything currently waiting on a connection
+    fn recv_all(conn: &dyn UorbConnection) -> Vec<(String, u64)> {
+        let mut received = vec![];
+        while let Some((header, msg)) = conn.try_recv().unwrap() {
+            received.push((mavulator::router::topic_name(&msg).to_string(), header.timestamp));
+        }
+        received
+    }
+
+    /// The first matching rule decides, otherwise the default applies
+    #[test]
+    pub fn test_filter_rules() {
+        let filter = TopicFilter::deny_all()
+            .rule(Rule::deny("SensorGyro").instance(1))
+            .rule(Rule::allow("SensorGyro"));
+        assert!(filter.permits("SensorGyro", 0));
+        assert!(!filter.permits("SensorGyro", 1));
+        assert!(!filter.permits("SensorAccel", 0));
+
+        let filter = TopicFilter::allow_all().rule(Rule::deny("DifferentialPressure"));
+        assert!(filter.permits("SensorGyro", 3));
+        assert!(!filter.permits("DifferentialPressure", 0));
+    }
+
+    /// Each route should receive only the topics its filter permits
+    #[test]
+    pub fn test_router_fan_out() {
+        let (link, firmware_end) = connection::pair(None);
+        let (sink, sink_end) = connection::pair(None);
+
+        let mut router = Router::new();
+        router.add_route(Arc::new(link), TopicFilter::allow_all()
+            .rule(Rule::deny("DifferentialPressure"))
+            .rule(Rule::deny("VehicleStatus").instance(1)));
+        router.add_optional_route(Arc::new(sink), TopicFilter::deny_all()
+            .rule(Rule::allow("DifferentialPressure")));
+
+        router.send_batch(&[
+            test_shared::get_vehicle_status_msg(0, 1),
+            test_shared::get_vehicle_status_msg(1, 2),
+            get_diff_pressure_msg(3),
+        ]).unwrap();
+        let (header, msg) = test_shared::get_vehicle_status_msg(0, 4);
+        router.send(&header, &msg).unwrap();
+
+        assert_eq!(recv_all(&firmware_end), vec![
+            ("VehicleStatus".to_string(), 1),
+            ("VehicleStatus".to_string(), 4),
+        ]);
+        assert_eq!(recv_all(&sink_end), vec![("DifferentialPressure".to_string(), 3)]);
+
+        // recv reads from the primary link
+        let (header, msg) = test_shared::get_vehicle_status_msg(0, 5);
+        firmware_end.send(&header, &msg).unwrap();
+        assert_eq!(router.recv().unwrap().0.timestamp, 5);
+    }
+
+    /// Only failures on required routes should be reported
+    #[test]
+    pub fn test_router_route_failures() {
+        let (link, firmware_end) = connection::pair(None);
+        let (sink, sink_end) = connection::pair(None);
+
+        let mut router = Router::new();
+        router.add_route(Arc::new(link), TopicFilter::allow_all());
+        router.add_optional_route(Arc::new(sink), TopicFilter::allow_all());
+
+        drop(sink_end);
+        router.send_batch(&[test_shared::get_vehicle_status_msg(0, 1)]).expect("optional failure reported");
+        assert_eq!(recv_all(&firmware_end).len(), 1);
+
+        drop(firmware_end);
+        assert!(router.send_batch(&[test_shared::get_vehicle_status_msg(0, 2)]).is_err());
+    }
+
+}
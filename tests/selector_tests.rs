@@ -0,0 +1,156 @@
+extern crate mavulator;
+
+
+#[cfg(test)]
+mod test_selectors {
+    use mavulator::connection::{self, ConnectionError, Protocol};
+
+    #[test]
+    pub fn test_parse_valid_selectors() {
+        let (protocol, address) = connection::parse_selector("tcpout:127.0.0.1:4560").unwrap();
+        assert_eq!(protocol, Protocol::TcpOut);
+        assert_eq!(address, "127.0.0.1:4560");
+
+        let (protocol, address) = connection::parse_selector("serial:/dev/ttyUSB0:921600").unwrap();
+        assert_eq!(protocol, Protocol::Serial);
+        assert_eq!(address, "/dev/ttyUSB0:921600");
+
+        let addr = connection::parse_socket_addr("127.0.0.1:4560").unwrap();
+        assert_eq!(addr.port(), 4560);
+        let addr = connection::parse_socket_addr("[::1]:4560").unwrap();
+        assert!(addr.is_ipv6());
+
+        let (port_name, baud) = connection::parse_serial_settings("/dev/ttyUSB0:921600").unwrap();
+        assert_eq!(port_name, "/dev/ttyUSB0");
+        assert_eq!(baud, 921600);
+    }
+
+    #[test]
+    pub fn test_unknown_protocol() {
+        match connection::parse_selector("tcpot:127.0.0.1:4560") {
+            Err(ConnectionError::UnknownProtocol(_)) => {},
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match connection::parse_selector("127.0.0.1") {
+            Err(ConnectionError::UnknownProtocol(_)) => {},
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match connection::select_protocol("bogus:127.0.0.1:4560") {
+            Err(ConnectionError::UnknownProtocol(_)) => {},
+            Err(e) => panic!("unexpected error: {:?}", e),
+            Ok(_) => panic!("bogus protocol accepted"),
+        }
+    }
+
+    #[test]
+    pub fn test_not_reconnectable() {
+        use mavulator::connection::{Backoff, ConnectionSpec};
+
+        let spec = ConnectionSpec::new(Protocol::UdpOut, "127.0.0.1:4560");
+        match spec.open_reconnecting(Backoff::default()) {
+            Err(ConnectionError::NotReconnectable(Protocol::UdpOut)) => {},
+            Err(e) => panic!("unexpected error: {:?}", e),
+            Ok(_) => panic!("udpout opened as reconnecting"),
+        }
+    }
+
+    #[test]
+    pub fn test_invalid_host() {
+        match connection::parse_socket_addr(":4560") {
+            Err(ConnectionError::InvalidHost(_)) => {},
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match connection::parse_socket_addr("no-such-host.invalid:4560") {
+            Err(ConnectionError::AddressLookup(_)) => {},
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    pub fn test_invalid_port() {
+        match connection::parse_socket_addr("127.0.0.1") {
+            Err(ConnectionError::InvalidPort(_)) => {},
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match connection::parse_socket_addr("127.0.0.1:") {
+            Err(ConnectionError::InvalidPort(_)) => {},
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match connection::parse_socket_addr("127.0.0.1:99999") {
+            Err(ConnectionError::InvalidPort(_)) => {},
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match connection::select_protocol("tcpout:127.0.0.1:port") {
+            Err(ConnectionError::InvalidPort(_)) => {},
+            Err(e) => panic!("unexpected error: {:?}", e),
+            Ok(_) => panic!("bad port accepted"),
+        }
+    }
+
+    #[test]
+    pub fn test_invalid_serial_settings() {
+        match connection::parse_serial_settings("/dev/ttyUSB0") {
+            Err(ConnectionError::InvalidBaudRate(_)) => {},
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match connection::parse_serial_settings("/dev/ttyUSB0:fast") {
+            Err(ConnectionError::InvalidBaudRate(_)) => {},
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match connection::parse_serial_settings(":921600") {
+            Err(ConnectionError::InvalidPath(_)) => {},
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    pub fn test_connect_failure_is_io_error() {
+        // nothing should be listening on this port
+        match connection::select_protocol("tcpout:127.0.0.1:1") {
+            Err(ConnectionError::Io(_)) => {},
+            Err(e) => panic!("unexpected error: {:?}", e),
+            Ok(_) => panic!("connected to a closed port"),
+        }
+    }
+
+    #[test]
+    pub fn test_parse_spec_options() {
+        use std::time::Duration;
+        use mavulator::connection::ConnectionSpec;
+
+        let spec = ConnectionSpec::parse("tcpout:127.0.0.1:4560?read_timeout_ms=5&nodelay=true&sndbuf=65536")
+            .unwrap();
+        assert_eq!(spec.protocol, Protocol::TcpOut);
+        assert_eq!(spec.address, "127.0.0.1:4560");
+        assert_eq!(spec.options.read_timeout, Some(Duration::from_millis(5)));
+        assert_eq!(spec.options.nodelay, true);
+        assert_eq!(spec.options.send_buffer_size, Some(65536));
+        assert_eq!(spec.options.recv_buffer_size, None);
+
+        // without options, each protocol keeps its usual defaults
+        let spec: ConnectionSpec = "tcpin:0.0.0.0:4560".parse().unwrap();
+        assert_eq!(spec, ConnectionSpec::new(Protocol::TcpIn, "0.0.0.0:4560"));
+        assert_eq!(spec.options.read_timeout, None);
+
+        let spec = ConnectionSpec::parse("udpin:0.0.0.0:4560?read_timeout_ms=0").unwrap();
+        assert_eq!(spec.options.read_timeout, None);
+    }
+
+    #[test]
+    pub fn test_invalid_spec_options() {
+        use mavulator::connection::ConnectionSpec;
+
+        for selector in &[
+            "tcpout:127.0.0.1:4560?bogus=1",
+            "tcpout:127.0.0.1:4560?nodelay=maybe",
+            "tcpout:127.0.0.1:4560?sndbuf",
+            "tcpout:127.0.0.1:4560?read_timeout_ms=-5",
+        ] {
+            match ConnectionSpec::parse(selector) {
+                Err(ConnectionError::InvalidOption(_)) => {},
+                other => panic!("unexpected result for {}: {:?}", selector, other),
+            }
+        }
+    }
+
+}
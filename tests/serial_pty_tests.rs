@@ -0,0 +1,56 @@
+extern crate mavulator;
+
+mod test_shared;
+
+
+#[cfg(all(test, unix))]
+mod test_serial_connections {
+    use std::io::Write;
+    use crate::test_shared;
+    use mavulator::connection::{SerialConnection, UorbConnection};
+    use serialport::posix::TTYPort;
+
+    fn get_vehicle_status_frame() -> Vec<u8> {
+        let (header, msg) = test_shared::get_vehicle_status_msg(17, 666);
+        test_shared::encode_frame(&header, &msg)
+    }
+
+    /// Test whether we can receive messages across a pseudo-terminal pair
+    #[test]
+    pub fn test_serial_pty_loopback() {
+        let (mut master, slave) = TTYPort::pair().expect("Couldn't create pty pair");
+        let conn = SerialConnection::new(Box::new(slave)).expect("Couldn't wrap pty");
+
+        let frame = get_vehicle_status_frame();
+        master.write_all(&frame).unwrap();
+        master.write_all(&frame).unwrap();
+
+        for _i in 0..2 {
+            let (header, msg) = conn.recv().expect("recv failed");
+            assert_eq!(header.timestamp, 666);
+            match msg {
+                uorb_codec::common::UorbMessage::VehicleStatus(_) => {},
+                _ => panic!("unexpected message type"),
+            }
+        }
+    }
+
+    /// Garbage ahead of and between frames should be skipped
+    #[test]
+    pub fn test_serial_resync_after_garbage() {
+        let (mut master, slave) = TTYPort::pair().expect("Couldn't create pty pair");
+        let conn = SerialConnection::new(Box::new(slave)).expect("Couldn't wrap pty");
+
+        let frame = get_vehicle_status_frame();
+        master.write_all(&[0x00, 0x13, 0xFF, 0x42, 0x07]).unwrap();
+        master.write_all(&frame).unwrap();
+        master.write_all(&[0x01, 0x02, 0x03]).unwrap();
+        master.write_all(&frame).unwrap();
+
+        let (header, _msg) = conn.recv().expect("first recv failed");
+        assert_eq!(header.timestamp, 666);
+        let (header, _msg) = conn.recv().expect("second recv failed");
+        assert_eq!(header.timestamp, 666);
+    }
+
+}
@@ -0,0 +1,25 @@
+extern crate mavulator;
+
+
+#[cfg(test)]
+mod test_sim_clock {
+    use mavulator::sim_clock::SimClock;
+
+    #[test]
+    pub fn test_advance() {
+        let mut clock = SimClock::default();
+        assert_eq!(clock.advance(5_000_000), (0.0, false));
+        assert_eq!(clock.advance(5_500_000), (0.5, false));
+        assert_eq!(clock.advance(5_500_000), (0.0, false));
+    }
+
+    /// Time running backwards is a restart, from which time is measured again
+    #[test]
+    pub fn test_restart() {
+        let mut clock = SimClock::default();
+        clock.advance(10_000_000);
+        assert_eq!(clock.advance(1_000_000), (0.0, true));
+        assert_eq!(clock.advance(3_000_000), (2.0, false));
+    }
+
+}
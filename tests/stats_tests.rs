@@ -0,0 +1,47 @@
+extern crate mavulator;
+
+mod test_shared;
+
+
+#[cfg(test)]
+mod test_stats {
+    use crate::test_shared;
+    use mavulator::connection::{self, UorbConnection};
+    use mavulator::stats::StatsConnection;
+    use uorb_codec::common::*;
+    use uorb_codec::{UorbMsgMeta};
+
+    /// Counts should be kept per topic and per direction, along with timeouts
+    #[test]
+    pub fn test_counts_per_topic_and_direction() {
+        let (sim_end, firmware_end) = connection::pair(None);
+        let conn = StatsConnection::new(sim_end);
+
+        let (header, msg) = test_shared::get_vehicle_status_msg(17, 666);
+
+        for _i in 0..3 {
+            conn.send(&header, &msg).unwrap();
+        }
+        firmware_end.send(&uorb_codec::UorbHeader { timestamp: 777, ..header }, &msg).unwrap();
+        conn.recv().unwrap();
+        // nothing else pending: this one times out
+        assert!(conn.recv().is_err());
+
+        let snapshot = conn.handle().snapshot();
+        let outbound = snapshot.outbound.topics.get("VehicleStatus").expect("no outbound VehicleStatus");
+        assert_eq!(outbound.count, 3);
+        assert!(outbound.bytes >= 3 * VehicleStatusData::ENCODED_LEN as u64);
+        assert_eq!(outbound.last_timestamp, 666);
+
+        let inbound = snapshot.inbound.topics.get("VehicleStatus").expect("no inbound VehicleStatus");
+        assert_eq!(inbound.count, 1);
+        assert_eq!(inbound.last_timestamp, 777);
+        assert_eq!(snapshot.inbound.timeouts, 1);
+        assert_eq!(snapshot.inbound.errors, 0);
+        assert_eq!(snapshot.outbound.errors, 0);
+
+        conn.handle().reset();
+        assert_eq!(conn.snapshot().outbound.total_count(), 0);
+    }
+
+}
@@ -6,66 +6,253 @@ mod test_shared;
 #[cfg(test)]
 mod test_tcp_connections {
     use std::thread;
+    use std::time::Duration;
     use crate::test_shared;
-    use uorb_codec::common::*;
-    use uorb_codec::{UorbMsgMeta};
 
     /// Test whether we can send a message via TCP and receive it OK
     #[test]
     pub fn test_tcp_loopback() {
-        const RECEIVE_CHECK_COUNT: i32 = 5;
+        const RECEIVE_CHECK_COUNT: usize = 5;
 
         let server_thread = thread::spawn( {
             move || {
                 let server = mavulator::connection::select_protocol("tcpin:0.0.0.0:14550")
                     .expect("Couldn't create server");
-
-                let mut recv_count = 0;
-                for _i in 0..RECEIVE_CHECK_COUNT {
-                    match server.recv() {
-                        Ok((_header, msg)) => {
-                            match msg {
-                                uorb_codec::common::UorbMessage::VehicleStatus(_vehicle_status_msg) => {
-                                    recv_count += 1;
-
-                                },
-                                _ => {
-                                    // one message parse failure fails the test
-                                    break;
-                                }
-                            }
-                        }
-                        Err(..) => {
-                            // one message read failure fails the test
-                            break;
-                        }
-                    }
-                }
-                assert_eq!(recv_count, RECEIVE_CHECK_COUNT);
+                assert_eq!(test_shared::recv_vehicle_status(&*server, RECEIVE_CHECK_COUNT), RECEIVE_CHECK_COUNT);
             }
         });
 
         // have the client send a few hearbeats
         thread::spawn({
             move || {
-                let msg_data = test_shared::get_vehicle_status();
-                let msg = uorb_codec::common::UorbMessage::VehicleStatus( msg_data.clone() );
-                let header = uorb_codec::UorbHeader {
-                    version: uorb_codec::UORB_MAGIC_V1,
-                    hash: VehicleStatusData::MSG_HASH_CODE,
-                    timestamp: 666,
-                    instance_id: 17,
-                    payload_len: VehicleStatusData::ENCODED_LEN,
-                };
                 let client = mavulator::connection::select_protocol("tcpout:127.0.0.1:14550")
                     .expect("Couldn't create client");
-                for _i in 0..RECEIVE_CHECK_COUNT {
-                    client.send(&header,&msg).ok();
-                }
+                test_shared::send_vehicle_status(&*client, RECEIVE_CHECK_COUNT, Duration::from_millis(0));
+            }
+        });
+
+        server_thread.join().unwrap();
+    }
+
+    /// Test that a reconnecting client survives the server dropping it,
+    /// and sends its handshake first on the new link
+    #[test]
+    pub fn test_tcp_reconnect() {
+        use std::net::TcpListener;
+        use std::sync::{Arc, Mutex};
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use mavulator::connection::{self, Backoff, ConnectionState, UorbConnection};
+
+        const HANDSHAKE_TIMESTAMP: u64 = 999;
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Couldn't bind");
+        let addr = listener.local_addr().unwrap();
+        let done = Arc::new(AtomicBool::new(false));
+
+        let server_thread = thread::spawn({
+            let done = done.clone();
+            move || {
+                // drop the first client immediately
+                let (first, _addr) = listener.accept().unwrap();
+                drop(first);
+
+                let (mut second, _addr) = listener.accept().unwrap();
+                let (header, _msg) = uorb_codec::read_msg(&mut second).expect("read failed");
+                done.store(true, Ordering::SeqCst);
+                assert_eq!(header.timestamp, HANDSHAKE_TIMESTAMP);
             }
         });
 
+        let (header, msg) = test_shared::get_vehicle_status_msg(17, 666);
+
+        let states = Arc::new(Mutex::new(vec![]));
+        let mut client = connection::tcpout_reconnecting(addr, Backoff::default())
+            .expect("Couldn't create client");
+        client.set_on_connect({
+            let msg = msg.clone();
+            let header = uorb_codec::UorbHeader { timestamp: HANDSHAKE_TIMESTAMP, ..header };
+            move |c: &dyn UorbConnection| c.send(&header, &msg)
+        });
+        client.set_state_listener({
+            let states = states.clone();
+            move |state| states.lock().unwrap().push(state)
+        });
+
+        while !done.load(Ordering::SeqCst) {
+            client.send(&header, &msg).expect("send failed");
+            thread::sleep(Duration::from_millis(10));
+        }
         server_thread.join().unwrap();
+
+        let states = states.lock().unwrap();
+        assert_eq!(states.first(), Some(&ConnectionState::Disconnected));
+        assert_eq!(states.last(), Some(&ConnectionState::Connected));
+    }
+
+    /// Test that a multi-client server broadcasts to every client,
+    /// merges their inbound traffic, and survives a client going away
+    #[test]
+    pub fn test_tcp_server_broadcast() {
+        use std::net::TcpStream;
+        use mavulator::connection::{self, UorbConnection};
+
+        let server = connection::tcpserver("127.0.0.1:0").expect("Couldn't create server");
+        let mut client_a = TcpStream::connect(server.local_addr()).unwrap();
+        let mut client_b = TcpStream::connect(server.local_addr()).unwrap();
+        while server.client_count() < 2 {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let (header, msg) = test_shared::get_vehicle_status_msg(17, 666);
+
+        server.send(&header, &msg).expect("broadcast failed");
+        for client in vec![&mut client_a, &mut client_b] {
+            let (recv_header, _msg) = uorb_codec::read_msg(client).expect("client read failed");
+            assert_eq!(recv_header.timestamp, 666);
+        }
+
+        uorb_codec::write_msg(&mut client_a, &header, &msg).unwrap();
+        uorb_codec::write_msg(&mut client_b, &header, &msg).unwrap();
+        for _i in 0..2 {
+            let mut res = server.recv();
+            while res.is_err() {
+                res = server.recv();
+            }
+            assert_eq!(res.unwrap().0.timestamp, 666);
+        }
+
+        drop(client_a);
+        while server.client_count() > 1 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        server.send(&header, &msg).expect("broadcast failed");
+        let (recv_header, _msg) = uorb_codec::read_msg(&mut client_b).expect("client read failed");
+        assert_eq!(recv_header.timestamp, 666);
+    }
+
+    /// Dropping a multi-client server stops it listening and disconnects its clients
+    #[test]
+    pub fn test_tcp_server_shutdown() {
+        use std::io::Read;
+        use std::net::TcpStream;
+        use std::time::Instant;
+        use mavulator::connection;
+
+        let server = connection::tcpserver("127.0.0.1:0").expect("Couldn't create server");
+        let addr = server.local_addr();
+        let mut client = TcpStream::connect(addr).unwrap();
+        while server.client_count() < 1 {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        drop(server);
+        client.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let mut buf = [0u8; 1];
+        assert_eq!(client.read(&mut buf).expect("client not disconnected"), 0);
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while TcpStream::connect(addr).is_ok() {
+            assert!(Instant::now() < deadline, "server still accepting");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn get_vehicle_status_frame(timestamp: u64, hash_valid: bool) -> Vec<u8> {
+        let (mut header, msg) = test_shared::get_vehicle_status_msg(17, timestamp);
+        if !hash_valid {
+            header.hash = !header.hash;
+        }
+        test_shared::encode_frame(&header, &msg)
+    }
+
+    /// Receive the next message, riding out read timeouts
+    fn recv_within(conn: &dyn mavulator::connection::UorbConnection, limit: Duration) -> u64 {
+        let deadline = std::time::Instant::now() + limit;
+        loop {
+            match conn.recv() {
+                Ok((header, _msg)) => return header.timestamp,
+                Err(ref e) if mavulator::connection::is_timeout(e) => {
+                    assert!(std::time::Instant::now() < deadline, "no message received");
+                },
+                Err(e) => panic!("recv failed: {:?}", e),
+            }
+        }
+    }
+
+    /// Garbage and frames with unknown hashes should be skipped, and counted
+    #[test]
+    pub fn test_tcp_resync_after_garbage() {
+        use std::io::Write;
+        use std::net::TcpListener;
+        use mavulator::connection::{self, UorbConnection};
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Couldn't bind");
+        let client = connection::tcpout(listener.local_addr().unwrap()).expect("Couldn't create client");
+        let (mut peer, _addr) = listener.accept().unwrap();
+
+        let garbage = [0x00, 0x13, 0xFF, 0x42, 0x07];
+        let unknown = get_vehicle_status_frame(2, false);
+        peer.write_all(&garbage).unwrap();
+        peer.write_all(&get_vehicle_status_frame(1, true)).unwrap();
+        peer.write_all(&unknown).unwrap();
+        peer.write_all(&garbage).unwrap();
+        peer.write_all(&get_vehicle_status_frame(3, true)).unwrap();
+
+        assert_eq!(recv_within(&client, Duration::from_secs(2)), 1);
+        assert_eq!(recv_within(&client, Duration::from_secs(2)), 3);
+        assert_eq!(client.skipped_bytes(), (2 * garbage.len() + unknown.len()) as u64);
+    }
+
+    /// A truncated frame may take the following frame with it, but no more
+    #[test]
+    pub fn test_tcp_resync_after_truncated_frame() {
+        use std::io::Write;
+        use std::net::TcpListener;
+        use mavulator::connection::{self, UorbConnection};
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Couldn't bind");
+        let client = connection::tcpout(listener.local_addr().unwrap()).expect("Couldn't create client");
+        let (mut peer, _addr) = listener.accept().unwrap();
+
+        let mut truncated = get_vehicle_status_frame(100, true);
+        let cut = truncated.len() - 4;
+        truncated.truncate(cut);
+        peer.write_all(&truncated).unwrap();
+        for i in 1..=5 {
+            peer.write_all(&get_vehicle_status_frame(i, true)).unwrap();
+        }
+
+        let mut received = vec![];
+        while received.last() != Some(&5) {
+            received.push(recv_within(&client, Duration::from_secs(2)));
+        }
+        assert!(received.ends_with(&[2, 3, 4, 5]), "received {:?}", received);
+        assert!(client.skipped_bytes() > 0);
+    }
+
+    /// A frame split across a read timeout should still be decoded whole
+    #[test]
+    pub fn test_tcp_frame_split_across_timeout() {
+        use std::io::Write;
+        use std::net::TcpListener;
+        use mavulator::connection::{self, UorbConnection};
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Couldn't bind");
+        let client = connection::tcpout(listener.local_addr().unwrap()).expect("Couldn't create client");
+        let (mut peer, _addr) = listener.accept().unwrap();
+
+        let frame = get_vehicle_status_frame(7, true);
+        let (head, tail) = frame.split_at(frame.len() / 2);
+        peer.write_all(head).unwrap();
+        peer.flush().unwrap();
+        match client.recv() {
+            Err(ref e) if connection::is_timeout(e) => {},
+            other => panic!("unexpected result {:?}", other.map(|(h, _m)| h.timestamp)),
+        }
+        peer.write_all(tail).unwrap();
+
+        assert_eq!(recv_within(&client, Duration::from_secs(2)), 7);
+        assert_eq!(client.skipped_bytes(), 0);
     }
 
-}
\ No newline at end of file
+}
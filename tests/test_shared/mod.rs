@@ -30,3 +30,53 @@ pub fn get_vehicle_status() -> uorb_codec::common::VehicleStatusData {
     }
 }
 
+/// A vehicle status message, with the header it is framed with
+#[allow(dead_code)]
+pub fn get_vehicle_status_msg(instance_id: u8, timestamp: u64) -> (uorb_codec::UorbHeader, uorb_codec::common::UorbMessage) {
+    use uorb_codec::UorbMsgMeta;
+
+    let msg = uorb_codec::common::UorbMessage::VehicleStatus( get_vehicle_status() );
+    let header = uorb_codec::UorbHeader {
+        version: uorb_codec::UORB_MAGIC_V1,
+        hash: uorb_codec::common::VehicleStatusData::MSG_HASH_CODE,
+        timestamp,
+        instance_id,
+        payload_len: uorb_codec::common::VehicleStatusData::ENCODED_LEN,
+    };
+    (header, msg)
+}
+
+/// Encode one framed message, as it appears on the wire
+#[allow(dead_code)]
+pub fn encode_frame(header: &uorb_codec::UorbHeader, msg: &uorb_codec::common::UorbMessage) -> Vec<u8> {
+    let mut frame = Vec::new();
+    uorb_codec::write_msg(&mut frame, header, msg).unwrap();
+    frame
+}
+
+/// Send `count` vehicle status messages, pausing for `interval` after each
+#[allow(dead_code)]
+pub fn send_vehicle_status(conn: &dyn mavulator::connection::UorbConnection, count: usize, interval: std::time::Duration) {
+    let (header, msg) = get_vehicle_status_msg(17, 666);
+    for _i in 0..count {
+        conn.send(&header, &msg).ok();
+        std::thread::sleep(interval);
+    }
+}
+
+/// Receive up to `count` messages, returning how many were vehicle status messages.
+/// Stops early at the first read failure or unexpected message.
+#[allow(dead_code)]
+pub fn recv_vehicle_status(conn: &dyn mavulator::connection::UorbConnection, count: usize) -> usize {
+    let mut recv_count = 0;
+    for _i in 0..count {
+        match conn.recv() {
+            Ok((_header, uorb_codec::common::UorbMessage::VehicleStatus(_vehicle_status_msg))) => {
+                recv_count += 1;
+            },
+            // one message parse or read failure fails the test
+            _ => break,
+        }
+    }
+    recv_count
+}
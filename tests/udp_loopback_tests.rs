@@ -0,0 +1,71 @@
+extern crate mavulator;
+
+mod test_shared;
+
+
+#[cfg(test)]
+mod test_udp_connections {
+    use std::thread;
+    use std::time::Duration;
+    use crate::test_shared;
+    use mavulator::connection::UorbConnection;
+
+    /// Test whether we can send a message via UDP and receive it OK
+    #[test]
+    pub fn test_udp_loopback() {
+        const RECEIVE_CHECK_COUNT: usize = 5;
+
+        // bind the server before the client starts sending, since datagrams are not queued
+        let server = mavulator::connection::udpin("127.0.0.1:0")
+            .expect("Couldn't create server");
+        let client_selector = format!("udpout:{}", server.local_addr().unwrap());
+
+        let server_thread = thread::spawn( {
+            move || {
+                assert_eq!(test_shared::recv_vehicle_status(&server, RECEIVE_CHECK_COUNT), RECEIVE_CHECK_COUNT);
+            }
+        });
+
+        // have the client send a few messages
+        thread::spawn({
+            move || {
+                let client = mavulator::connection::select_protocol(&client_selector)
+                    .expect("Couldn't create client");
+                test_shared::send_vehicle_status(&*client, RECEIVE_CHECK_COUNT, Duration::from_millis(10));
+            }
+        });
+
+        server_thread.join().unwrap();
+    }
+
+    /// A truncated datagram should be skipped rather than ending the receive loop
+    #[test]
+    pub fn test_udp_truncated_datagram() {
+        let server = mavulator::connection::udpin("127.0.0.1:0")
+            .expect("Couldn't create server");
+        let server_addr = server.local_addr().unwrap();
+
+        let (header, msg) = test_shared::get_vehicle_status_msg(17, 666);
+        let frame = test_shared::encode_frame(&header, &msg);
+
+        let raw = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        raw.send_to(&frame[..frame.len() / 2], server_addr).unwrap();
+        raw.send_to(&frame, server_addr).unwrap();
+
+        let (recv_header, _msg) = server.recv().expect("recv failed");
+        assert_eq!(recv_header.timestamp, 666);
+    }
+
+    /// A server cannot reply before any peer has sent to it, and says so rather than dropping the message
+    #[test]
+    pub fn test_udp_send_before_peer() {
+        let server = mavulator::connection::udpin("127.0.0.1:0")
+            .expect("Couldn't create server");
+        let (header, msg) = test_shared::get_vehicle_status_msg(17, 666);
+        match server.send(&header, &msg) {
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotConnected => {},
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+}
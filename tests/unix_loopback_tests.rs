@@ -0,0 +1,63 @@
+extern crate mavulator;
+
+mod test_shared;
+
+
+#[cfg(all(test, unix))]
+mod test_unix_connections {
+    use std::thread;
+    use std::time::Duration;
+    use crate::test_shared;
+
+    /// Test whether we can send a message via a unix domain socket and receive it OK
+    #[test]
+    pub fn test_unix_loopback() {
+        const RECEIVE_CHECK_COUNT: usize = 5;
+        // unique to this run, so concurrent runs don't bind each other's socket
+        let socket_path = std::env::temp_dir()
+            .join(format!("mavulator_unix_loopback_{}.sock", std::process::id()));
+        let server_selector = format!("unixin:{}", socket_path.display());
+        let client_selector = format!("unix:{}", socket_path.display());
+
+        let server_thread = thread::spawn( {
+            move || {
+                let server = mavulator::connection::select_protocol(&server_selector)
+                    .expect("Couldn't create server");
+                assert_eq!(test_shared::recv_vehicle_status(&*server, RECEIVE_CHECK_COUNT), RECEIVE_CHECK_COUNT);
+            }
+        });
+
+        // have the client send a few messages
+        thread::spawn({
+            move || {
+                // give the server a moment to bind
+                let mut client = mavulator::connection::select_protocol(&client_selector);
+                while client.is_err() {
+                    thread::sleep(Duration::from_millis(10));
+                    client = mavulator::connection::select_protocol(&client_selector);
+                }
+                let client = client.unwrap();
+                test_shared::send_vehicle_status(&*client, RECEIVE_CHECK_COUNT, Duration::from_millis(0));
+            }
+        });
+
+        server_thread.join().unwrap();
+        std::fs::remove_file(&socket_path).ok();
+    }
+
+    /// Binding never removes a file that isn't a stale socket
+    #[test]
+    pub fn test_unixin_keeps_regular_file() {
+        let path = std::env::temp_dir()
+            .join(format!("mavulator_unixin_regular_file_{}.sock", std::process::id()));
+        std::fs::write(&path, b"not a socket").unwrap();
+
+        match mavulator::connection::unixin(&path) {
+            Err(mavulator::connection::ConnectionError::InvalidPath(_)) => {},
+            other => panic!("expected InvalidPath, got {:?}", other.map(|_conn| ())),
+        }
+        assert_eq!(std::fs::read(&path).unwrap(), b"not a socket");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+}